@@ -0,0 +1,109 @@
+//! Animated output, for screen to camera transfers. Each frame shows a single code, large
+//! enough to be scanned by a phone, and the animation loops through the parts for as long as
+//! it's shown.
+
+use crate::encode::render_grid;
+use crate::error::{err_value_validation, io_error, Result};
+use crate::ClapResult;
+use image::{imageops, GrayImage};
+use qr_code::QrCode;
+use std::io::Write;
+
+const MIN_FRAME_RATE: f32 = 0.1;
+const MAX_FRAME_RATE: f32 = 50.0;
+
+/// Parses a frame rate, in frames per second.
+pub(crate) fn parse_frame_rate(s: &str) -> ClapResult<f32> {
+    match s.parse::<f32>() {
+        Ok(rate) if (MIN_FRAME_RATE..=MAX_FRAME_RATE).contains(&rate) => Ok(rate),
+        _ => Err(err_value_validation(format!(
+            "invalid frame rate {s}, it must be between {MIN_FRAME_RATE} and {MAX_FRAME_RATE}"
+        ))),
+    }
+}
+
+/// Writes the codes as an animated GIF, looping forever.
+pub(crate) fn write_as_gif(
+    qr_vec: &[(String, QrCode)],
+    frame_rate: f32,
+    out: &mut impl Write,
+) -> Result<()> {
+    let frames = frames(qr_vec);
+    let (width, height) = frames.first().map(|f| f.dimensions()).unwrap_or_default();
+    let (width, height) = (width as u16, height as u16);
+    // the frames are black and white only, so a fixed palette does, with no quantization
+    let palette = [255, 255, 255, 0, 0, 0];
+    let gif_error = |e: gif::EncodingError| io_error(e.to_string());
+    let mut encoder = gif::Encoder::new(out, width, height, &palette).map_err(gif_error)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(gif_error)?;
+    // GIF delays are in hundredths of a second
+    let delay = (frame_delay_ms(frame_rate) + 5) / 10;
+    for frame in &frames {
+        let indices = frame
+            .pixels()
+            .map(|pixel| u8::from(pixel[0] < 128))
+            .collect::<Vec<_>>();
+        let mut frame = gif::Frame::from_indexed_pixels(width, height, &indices, None);
+        frame.delay = delay;
+        encoder.write_frame(&frame).map_err(gif_error)?;
+    }
+    Ok(())
+}
+
+/// Writes the codes as an animated PNG, looping forever. Viewers without APNG support show the
+/// first part only.
+pub(crate) fn write_as_apng(
+    qr_vec: &[(String, QrCode)],
+    frame_rate: f32,
+    out: &mut impl Write,
+) -> Result<()> {
+    let frames = frames(qr_vec);
+    let (width, height) = frames.first().map(|f| f.dimensions()).unwrap_or_default();
+    let mut encoder = png::Encoder::new(out, width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let png_error = |e: png::EncodingError| io_error(e.to_string());
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(png_error)?;
+    encoder
+        .set_frame_delay(frame_delay_ms(frame_rate), 1000)
+        .map_err(png_error)?;
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    for frame in &frames {
+        writer.write_image_data(frame).map_err(png_error)?;
+    }
+    writer.finish().map_err(png_error)?;
+    Ok(())
+}
+
+fn frame_delay_ms(frame_rate: f32) -> u16 {
+    (1000.0 / frame_rate).round() as u16
+}
+
+/// Draws each code on a frame of its own. The frames are all the size of the largest one, as
+/// the last part may need a smaller code than the others.
+fn frames(qr_vec: &[(String, QrCode)]) -> Vec<GrayImage> {
+    let codes = qr_vec
+        .iter()
+        .map(|code| render_grid(std::slice::from_ref(code), 1))
+        .collect::<Vec<_>>();
+    let width = codes.iter().map(|c| c.width()).max().unwrap_or(0);
+    let height = codes.iter().map(|c| c.height()).max().unwrap_or(0);
+    codes
+        .into_iter()
+        .map(|code| {
+            if code.dimensions() == (width, height) {
+                return code;
+            }
+            let mut frame = GrayImage::new(width, height);
+            frame.fill(255);
+            let x = (width - code.width()) / 2;
+            let y = (height - code.height()) / 2;
+            imageops::replace(&mut frame, &code, x.into(), y.into());
+            frame
+        })
+        .collect()
+}
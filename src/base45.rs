@@ -0,0 +1,38 @@
+//! Base45 encoding, as per RFC 9285. Its alphabet is the one of the QR alphanumeric mode, which
+//! packs 2 characters in 11 bits, so encoded data takes ~25% fewer bits than base64 in byte
+//! mode.
+
+const ALPHABET: &[u8; 45] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+pub(crate) fn encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(2) * 3);
+    for chunk in data.chunks(2) {
+        let (mut n, digits) = match chunk {
+            [a, b] => ((*a as usize) << 8 | *b as usize, 3),
+            [a] => (*a as usize, 2),
+            _ => unreachable!(),
+        };
+        for _ in 0..digits {
+            encoded.push(ALPHABET[n % 45] as char);
+            n /= 45;
+        }
+    }
+    encoded
+}
+
+/// Reverses [encode], returning `None` if `text` isn't valid base45.
+pub(crate) fn decode(text: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::<u8>::with_capacity(text.len() / 3 * 2 + 1);
+    for chunk in text.as_bytes().chunks(3) {
+        let mut n = 0usize;
+        for c in chunk.iter().rev() {
+            n = n * 45 + ALPHABET.iter().position(|a| a == c)?;
+        }
+        match chunk.len() {
+            3 if n <= 0xffff => data.extend_from_slice(&(n as u16).to_be_bytes()),
+            2 if n <= 0xff => data.push(n as u8),
+            _ => return None,
+        }
+    }
+    Some(data)
+}
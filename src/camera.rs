@@ -10,7 +10,7 @@ use hyper::{
 use tokio::sync::mpsc::Sender;
 use tokio::sync::Mutex as AsyncMutex;
 
-use crate::{console, error::Result, QRSTREAM_MAGIC, QRSTREAM_VERSION};
+use crate::{console, error::Result, fountain, QRSTREAM_MAGIC, QRSTREAM_VERSION};
 
 lazy_static::lazy_static! {
     static ref CAM_DATA_TX: Arc<AsyncMutex<Option<Sender<String>>>> = <_>::default();
@@ -53,7 +53,8 @@ async fn handle_decode_get(request: Request<Body>) -> HyperResult<Response<Body>
             Response::new(Body::from(
                 SERVER_INDEX_HTML
                     .replace("MAGIC_PREFIX", &format!("\"{QRSTREAM_MAGIC}\""))
-                    .replace("CURRENT_VERSION", &format!("{QRSTREAM_VERSION}")),
+                    .replace("CURRENT_VERSION", &format!("{QRSTREAM_VERSION}"))
+                    .replace("MAX_FOUNTAIN_BLOCKS", &format!("{}", fountain::MAX_BLOCKS)),
             ))
         } else if request.uri() == "/data" && request.method() == hyper::Method::PUT {
             let body =
@@ -0,0 +1,104 @@
+//! AEAD ciphers the payload can be encrypted with.
+
+use crate::error::{err_invalid_input, err_value_validation, Result};
+use crate::ClapResult;
+use aes_gcm::{
+    aead::{generic_array::typenum::Unsigned, rand_core::OsRng, Aead, AeadCore, KeyInit, Payload},
+    Aes256Gcm,
+};
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Cipher {
+    #[default]
+    None,
+    Aes256Gcm,
+    ChaCha20Poly1305,
+    XChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub(crate) fn parse(s: &str) -> ClapResult<Self> {
+        match s {
+            "aes256gcm" => Ok(Self::Aes256Gcm),
+            "chacha20poly1305" => Ok(Self::ChaCha20Poly1305),
+            "xchacha20poly1305" => Ok(Self::XChaCha20Poly1305),
+            _ => Err(err_value_validation(format!("invalid cipher {s}"))),
+        }
+    }
+
+    pub(crate) fn id(&self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Aes256Gcm => 1,
+            Self::ChaCha20Poly1305 => 2,
+            Self::XChaCha20Poly1305 => 3,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Aes256Gcm),
+            2 => Ok(Self::ChaCha20Poly1305),
+            3 => Ok(Self::XChaCha20Poly1305),
+            _ => Err(err_value_validation("unsupported cipher"))?,
+        }
+    }
+
+    /// Length of the output of [Cipher::encrypt] for `data_len` bytes of input.
+    pub(crate) fn encrypted_len(&self, data_len: usize) -> usize {
+        match self {
+            Self::None => data_len,
+            Self::Aes256Gcm => sealed_len::<Aes256Gcm>(data_len),
+            Self::ChaCha20Poly1305 => sealed_len::<ChaCha20Poly1305>(data_len),
+            Self::XChaCha20Poly1305 => sealed_len::<XChaCha20Poly1305>(data_len),
+        }
+    }
+
+    /// Encrypts `data` with a random nonce, returning the nonce followed by the ciphertext.
+    pub(crate) fn encrypt(&self, key: &[u8; 32], data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Aes256Gcm => seal::<Aes256Gcm>(key, data, aad),
+            Self::ChaCha20Poly1305 => seal::<ChaCha20Poly1305>(key, data, aad),
+            Self::XChaCha20Poly1305 => seal::<XChaCha20Poly1305>(key, data, aad),
+        }
+    }
+
+    /// Reverses [Cipher::encrypt].
+    pub(crate) fn decrypt(&self, key: &[u8; 32], data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Aes256Gcm => open::<Aes256Gcm>(key, data, aad),
+            Self::ChaCha20Poly1305 => open::<ChaCha20Poly1305>(key, data, aad),
+            Self::XChaCha20Poly1305 => open::<XChaCha20Poly1305>(key, data, aad),
+        }
+    }
+}
+
+fn sealed_len<C: AeadCore>(data_len: usize) -> usize {
+    C::NonceSize::USIZE + data_len + C::TagSize::USIZE
+}
+
+fn seal<C: Aead + AeadCore + KeyInit>(key: &[u8; 32], data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let cipher = C::new_from_slice(key).map_err(|_| err_invalid_input())?;
+    let nonce = C::generate_nonce(&mut OsRng);
+    let mut sealed = nonce.to_vec();
+    sealed.extend(cipher.encrypt(&nonce, Payload { msg: data, aad })?);
+    Ok(sealed)
+}
+
+fn open<C: Aead + AeadCore + KeyInit>(key: &[u8; 32], data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let nonce_len = C::NonceSize::USIZE;
+    if data.len() < nonce_len {
+        Err(err_invalid_input())?;
+    }
+    let cipher = C::new_from_slice(key).map_err(|_| err_invalid_input())?;
+    let (nonce, ciphertext) = data.split_at(nonce_len);
+    let payload = Payload {
+        msg: ciphertext,
+        aad,
+    };
+    Ok(cipher.decrypt(nonce.into(), payload)?)
+}
@@ -0,0 +1,127 @@
+//! Compression of the data, applied before encryption.
+
+use crate::error::{err_value_validation, Result};
+use crate::memory::SecretVec;
+use crate::ClapResult;
+use flate2::{read::DeflateDecoder, write::DeflateEncoder};
+use std::io::{Read, Write};
+
+const ZSTD_LEVEL: i32 = 19;
+
+/// Upper bound of the size of decompressed data, whatever the stream says it is
+pub(crate) const MAX_DECOMPRESSED_LEN: u64 = 64 * 1024 * 1024;
+
+/// Compression algorithm the data has been compressed with, as recorded in the stream header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Compression {
+    #[default]
+    None,
+    Deflate,
+    Zstd,
+}
+
+impl Compression {
+    pub(crate) fn id(&self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Deflate => 1,
+            Self::Zstd => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Deflate),
+            2 => Ok(Self::Zstd),
+            _ => Err(err_value_validation("unsupported compression"))?,
+        }
+    }
+
+    /// Compresses `data`. The output is written to a [SecretVec], as it's as sensitive as the
+    /// data itself.
+    pub(crate) fn compress(&self, data: &[u8]) -> Result<SecretVec> {
+        let output = || SecretVec::with_capacity(data.len() / 2);
+        match self {
+            Self::None => Ok(SecretVec::new(data.to_vec())),
+            Self::Deflate => {
+                let mut encoder = DeflateEncoder::new(output(), flate2::Compression::best());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Self::Zstd => {
+                let mut output = output();
+                zstd::stream::copy_encode(data, &mut output, ZSTD_LEVEL)?;
+                Ok(output)
+            }
+        }
+    }
+
+    /// Returns a reader of `data` decompressed, reversing [Compression::compress]. As the
+    /// compressed data comes from the stream, whatever is read from it has to be bounded, e.g.
+    /// with [read_decompressed].
+    pub(crate) fn decoder<'a>(&self, data: &'a [u8]) -> Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Self::None => Box::new(data),
+            Self::Deflate => Box::new(DeflateDecoder::new(data)),
+            Self::Zstd => {
+                Box::new(zstd::stream::read::Decoder::with_buffer(data).map_err(invalid_data)?)
+            }
+        })
+    }
+}
+
+/// Reads the rest of the data from `decoder`, failing if there's more than `limit` bytes of it,
+/// so that a small stream can't decompress to more than memory can hold.
+pub(crate) fn read_decompressed(decoder: &mut impl Read, limit: u64) -> Result<SecretVec> {
+    let limit = limit.min(MAX_DECOMPRESSED_LEN);
+    let data =
+        SecretVec::read_from(&mut decoder.by_ref().take(limit + 1), 0).map_err(invalid_data)?;
+    if data.len() as u64 > limit {
+        Err(invalid_data(()))?;
+    }
+    Ok(data)
+}
+
+fn invalid_data<E>(_: E) -> clap::error::Error {
+    err_value_validation("invalid compressed data")
+}
+
+/// Compression asked for when encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CompressionMode {
+    Only(Compression),
+    /// Uses whichever compression gives the smallest output, or none if it doesn't shrink
+    /// the data
+    Auto,
+}
+
+impl CompressionMode {
+    pub(crate) fn parse(s: &str) -> ClapResult<Self> {
+        match s {
+            "none" => Ok(Self::Only(Compression::None)),
+            "deflate" => Ok(Self::Only(Compression::Deflate)),
+            "zstd" => Ok(Self::Only(Compression::Zstd)),
+            "auto" => Ok(Self::Auto),
+            _ => Err(err_value_validation(format!("invalid compression {s}"))),
+        }
+    }
+
+    /// Compresses `data`, returning the compression picked along with the compressed data. With
+    /// `auto`, the candidates that lose are wiped as they're dropped.
+    pub(crate) fn compress(&self, data: &[u8]) -> Result<(Compression, SecretVec)> {
+        match self {
+            Self::Only(compression) => Ok((*compression, compression.compress(data)?)),
+            Self::Auto => {
+                let mut best = (Compression::None, Compression::None.compress(data)?);
+                for compression in [Compression::Deflate, Compression::Zstd] {
+                    let compressed = compression.compress(data)?;
+                    if compressed.len() < best.1.len() {
+                        best = (compression, compressed);
+                    }
+                }
+                Ok(best)
+            }
+        }
+    }
+}
@@ -2,23 +2,33 @@
 
 use super::error::Result;
 use std::fs::File;
-use std::io::{BufRead, Write};
+use std::io::{Read, Write};
 use std::os::fd::{AsFd, AsRawFd};
+use zeroize::Zeroizing;
 
 /// Prints a message to the current terminal, if available.
 pub(crate) fn println(message: impl AsRef<str>) {
     if let Ok(mut file) = File::options().write(true).open("/dev/tty") {
         _ = file.write_all(message.as_ref().as_bytes());
-        _ = file.write(&[b'\n']);
+        _ = file.write(b"\n");
     }
 }
 
+/// Returns `true` if there's a terminal to prompt on.
+pub(crate) fn has_tty() -> bool {
+    File::options()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .is_ok()
+}
+
 /// Prompts the user for input. If `confidential` is set to `true`, the input is read
 /// as a password.
 pub(crate) fn prompt(message: impl AsRef<str>, confidential: bool) -> Result<String> {
     let mut file = File::options().read(true).write(true).open("/dev/tty")?;
     file.write_all(message.as_ref().as_bytes())?;
-    _ = file.write(&[b':', b' '])?;
+    _ = file.write(b": ")?;
     file.flush()?;
 
     let fd = file.as_fd();
@@ -35,11 +45,43 @@ pub(crate) fn prompt(message: impl AsRef<str>, confidential: bool) -> Result<Str
             return Err(std::io::Error::last_os_error().into());
         }
     }
-    let mut input = String::new();
-    let mut reader = std::io::BufReader::new(&mut file);
-    reader.read_line(&mut input)?;
+    // read a byte at a time, as a buffered reader would leave a copy of the input behind
+    let mut input = Zeroizing::new(Vec::<u8>::with_capacity(256));
+    let mut byte = [0u8; 1];
+    while file.read(&mut byte)? == 1 && byte[0] != b'\n' {
+        input.push(byte[0]);
+    }
     if confidential && unsafe { libc::tcsetattr(fd_raw, libc::TCSANOW, &old_attribs) } != 0 {
         return Err(std::io::Error::last_os_error().into());
     }
+    let input = std::str::from_utf8(&input)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
     Ok(input.trim().to_string())
 }
+
+/// Waits for a key to be pressed on the terminal, returning the first byte it sent. Signals
+/// aren't generated while waiting, so Ctrl-C comes back as is.
+pub(crate) fn read_key() -> Result<u8> {
+    let mut file = File::options().read(true).write(true).open("/dev/tty")?;
+    let fd_raw = file.as_raw_fd();
+    let mut old_attribs: libc::termios = unsafe { std::mem::MaybeUninit::zeroed().assume_init() };
+    if unsafe { libc::tcgetattr(fd_raw, &mut old_attribs) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let mut new_attribs = old_attribs;
+    new_attribs.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+    new_attribs.c_cc[libc::VMIN] = 1;
+    new_attribs.c_cc[libc::VTIME] = 0;
+    if unsafe { libc::tcsetattr(fd_raw, libc::TCSANOW, &new_attribs) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // keys like the arrows send several bytes at once, which are all read so that none is left
+    // over for the next call
+    let mut key = [0u8; 16];
+    let read = file.read(&mut key);
+    if unsafe { libc::tcsetattr(fd_raw, libc::TCSANOW, &old_attribs) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    read?;
+    Ok(key[0])
+}
@@ -1,38 +1,69 @@
+use crate::base45;
+use crate::cipher::Cipher;
+use crate::compress::{self, MAX_DECOMPRESSED_LEN};
 use crate::error::{err_invalid_input, err_value_validation};
-use crate::{QRStreamOptions, QRSTREAM_MAGIC, QRSTREAM_VERSION};
+use crate::fountain;
+use crate::header::StreamHeader;
+use crate::kdf::{key_check, Kdf};
+use crate::memory::{SecretKey, SecretVec};
+use crate::metadata::Metadata;
+use crate::recipient::Identity;
+use crate::shamir;
+use crate::signature;
+use crate::structured_append;
+use crate::{
+    create_private_file, part_checksum, PasswordSource, QRStreamOptions, QRSTREAM_MAGIC,
+    QRSTREAM_VERSION, QRSTREAM_VERSION_V1,
+};
 
 use super::error::Result;
 
-use aes_gcm::Nonce;
-use aes_gcm::{
-    aead::{Aead, KeyInit},
-    Aes256Gcm, Key,
-};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use std::collections::BTreeMap;
 use std::io::Write;
 
+/// Maximum number of data and parity shards of a Reed-Solomon code over GF(2^8)
+const MAX_SHARDS: usize = 256;
+
 pub(crate) async fn decode(options: &super::QRStreamOptions) -> Result<()> {
-    let recvd_data = options.input.get_content().await?;
+    let mut texts = Vec::<String>::new();
+    for input in &options.input {
+        texts.push(read_text(&input.get_content().await?)?);
+    }
+    decode_data(texts.join("\n"), options)
+}
+
+/// Reads the text of the parts in `recvd_data`, which is either text, or a PNG with QR codes.
+fn read_text(recvd_data: &[u8]) -> Result<String> {
     let is_png = recvd_data.len() > 8 && recvd_data.starts_with(b"\x89PNG\x0d\x0a\x1a\x0a");
-    let raw_text = if is_png {
-        let image = image::load_from_memory(&recvd_data)
+    if is_png {
+        let image = image::load_from_memory(recvd_data)
             .map_err(|_| err_invalid_input())?
             .to_luma8();
         let mut img = rqrr::PreparedImage::prepare(image);
         let grids = img.detect_grids();
-        let mut text = String::new();
+        let mut contents = Vec::<String>::new();
+        let mut sa_parts = Vec::<structured_append::Part>::new();
         for g in grids {
-            let (_, content) = g.decode().map_err(|_| err_invalid_input())?;
-            if !text.is_empty() {
-                text.push('\n');
+            match g.decode() {
+                Ok((_, content)) => contents.push(content),
+                // rqrr can't read structured append codes, so those are read separately. Other
+                // codes that can't be read are skipped, as parity parts may make up for them
+                Err(_) => sa_parts.extend(structured_append::read(&g.grid)),
             }
-            text += &content;
         }
-        text
+        contents.extend(structured_append::merge(sa_parts)?);
+        Ok(contents
+            .iter()
+            .map(|content| content.trim_end_matches('\n'))
+            .collect::<Vec<_>>()
+            .join("\n"))
     } else {
-        String::from_utf8(recvd_data).map_err(|_| err_invalid_input())?
-    };
-    decode_data(raw_text, options)
+        Ok(std::str::from_utf8(recvd_data)
+            .map_err(|_| err_invalid_input())?
+            .to_string())
+    }
 }
 
 fn decode_data(raw_text: impl AsRef<str>, options: &QRStreamOptions) -> Result<()> {
@@ -42,31 +73,181 @@ fn decode_data(raw_text: impl AsRef<str>, options: &QRStreamOptions) -> Result<(
         Err(err_invalid_input())?;
     }
 
-    let input = assemble_text(raw_text)?;
+    let (version, data) = assemble_text(raw_text)?;
 
-    // decode text
-    let data = URL_SAFE_NO_PAD
-        .decode(input)
-        .map_err(|_| err_invalid_input())?;
-    let msg_data = if let Some(key) = &options.key {
-        let nonce_len = (data[0] & 0x1f) as usize;
-        let nonce = Nonce::from_slice(&data[1..1 + nonce_len]);
-        let key = Key::<Aes256Gcm>::from_slice(key);
-        let cipher = Aes256Gcm::new(key);
-        cipher
-            .decrypt(nonce, &data[1 + nonce_len..])
-            .map_err(|_| err_invalid_input())?
+    let verify_key = options
+        .decode_options()
+        .verify_key
+        .as_deref()
+        .map(signature::read_verifying_key)
+        .transpose()?;
+    let (metadata, msg_data) = if version == QRSTREAM_VERSION_V1 {
+        if verify_key.is_some() {
+            Err(err_value_validation("stream is not signed"))?;
+        }
+        let msg_data = SecretVec::new(if options.secrets.is_empty() {
+            data
+        } else {
+            options
+                .secrets
+                .iter()
+                .find_map(|secret| {
+                    let key = secret.legacy_key().ok()?;
+                    Cipher::Aes256Gcm
+                        .decrypt(&key, data.get(1..).unwrap_or_default(), &[])
+                        .ok()
+                })
+                .ok_or_else(err_invalid_input)?
+        });
+        (None, msg_data)
     } else {
-        data
+        let (header, body) = StreamHeader::parse(&data)?;
+        if let Some(verify_key) = &verify_key {
+            signature::verify(verify_key, &header.signed_data(body), &header.signature)?;
+        }
+        let decrypted = SecretVec::new(match (header.cipher, options.secrets.is_empty()) {
+            (Cipher::None, true) => body.to_vec(),
+            (Cipher::None, false) => Err(err_value_validation(
+                "stream is not encrypted, decode it without a password",
+            ))?,
+            (cipher, _) => {
+                let identity = options.decode_options().identity.as_deref();
+                let key = payload_key(&header, options, identity)?;
+                if key_check(&key) != header.key_check {
+                    Err(err_value_validation("incorrect password"))?;
+                }
+                let aad = header.associated_data();
+                // the key is known to be right at this point, so a failure can only be due to
+                // a header that doesn't match the one the payload was encrypted with, or to a
+                // body modified along with its digest. The AEAD can't tell those apart
+                cipher
+                    .decrypt(&key, body, &aad)
+                    .map_err(|_| err_value_validation("header or data tampered"))?
+            }
+        });
+        let mut decoder = header.compression.decoder(&decrypted)?;
+        if header.metadata {
+            let metadata = Metadata::read(&mut decoder)?;
+            let data = compress::read_decompressed(&mut decoder, metadata.length)?;
+            if data.len() as u64 != metadata.length {
+                Err(err_value_validation("file length mismatch"))?;
+            }
+            (Some(metadata), data)
+        } else {
+            let data = compress::read_decompressed(&mut decoder, MAX_DECOMPRESSED_LEN)?;
+            (None, data)
+        }
     };
-    std::io::stdout().write_all(&msg_data)?;
+
+    if options.decode_options().restore_name {
+        let metadata =
+            metadata.ok_or_else(|| err_value_validation("stream has no file metadata"))?;
+        let path = metadata.restore_path()?;
+        let mut file = create_private_file(&path)?;
+        file.write_all(&msg_data)?;
+    } else {
+        std::io::stdout().write_all(&msg_data)?;
+    }
 
     Ok(())
 }
 
-fn assemble_text(input: &str) -> Result<String> {
-    let mut num_parts = 0;
-    let mut part_list = Vec::<(u8, &str)>::new();
+/// Returns the key of the payload of an encrypted stream, unlocked with one of the passwords, or
+/// with the identity in the file at `identity` for streams encrypted to recipients. The password
+/// is prompted for if none is given.
+fn payload_key(
+    header: &StreamHeader,
+    options: &QRStreamOptions,
+    identity: Option<&str>,
+) -> Result<SecretKey> {
+    let secrets = options.secrets.as_slice();
+    if let Some(path) = identity {
+        if header.recipients.is_empty() {
+            Err(err_value_validation(
+                "stream is not encrypted to a recipient, decode it with a password instead",
+            ))?;
+        }
+        let identities = Identity::read_file(path)?;
+        return Ok(header
+            .recipients
+            .iter()
+            .find_map(|wrapped_key| {
+                identities
+                    .iter()
+                    .find_map(|identity| identity.unwrap(wrapped_key))
+            })
+            .ok_or_else(|| err_value_validation("stream isn't encrypted to this identity"))?);
+    }
+    if !header.recipients.is_empty() && header.key_slots.is_empty() {
+        Err(err_value_validation(if secrets.is_empty() {
+            "stream is encrypted to recipients, decode it with --identity"
+        } else {
+            "stream is encrypted to recipients, decode it with --identity instead of a password"
+        }))?;
+    }
+
+    let prompted_secrets;
+    let secrets = if secrets.is_empty() {
+        let description = options.password_description();
+        prompted_secrets = [PasswordSource::Prompt.get_secret(&description)?];
+        &prompted_secrets
+    } else {
+        secrets
+    };
+    for secret in secrets {
+        if header.key_slots.is_empty() {
+            // the key is derived from the password directly
+            let kdf = header.kdf.as_ref().unwrap_or(&Kdf::LEGACY);
+            let key = secret.derive_key(kdf, &header.salt)?;
+            if key_check(&key) == header.key_check {
+                return Ok(key);
+            }
+        } else {
+            for key_slot in &header.key_slots {
+                if let Some(key) = key_slot.unwrap(secret, header.cipher)? {
+                    return Ok(key);
+                }
+            }
+        }
+    }
+    Err(err_value_validation("incorrect password"))?
+}
+
+/// A part of a stream, as parsed from one line of text.
+struct StreamPart<'a> {
+    /// Zero based index of the part, or of the symbol for fountain coded parts
+    index: usize,
+    /// Total number of parts, or of blocks for fountain coded parts
+    total: usize,
+    fountain: bool,
+    parity: usize,
+    checksum: Option<u16>,
+    text: &'a str,
+    /// Whether `text` is base45 (the `a=` section), rather than base64url (the `t=` section)
+    base45: bool,
+    /// x coordinate and threshold of the Shamir share the part belongs to, if any
+    share: Option<(u8, usize)>,
+}
+
+impl StreamPart<'_> {
+    /// Returns the data carried by this part, or `None` if it's corrupt.
+    fn data(&self) -> Option<Vec<u8>> {
+        if self.checksum.is_some_and(|c| c != part_checksum(self.text)) {
+            None
+        } else if self.base45 {
+            base45::decode(self.text)
+        } else {
+            URL_SAFE_NO_PAD.decode(self.text).ok()
+        }
+    }
+}
+
+/// Assembles the parts in `input` back into the payload, returning it along with the version
+/// of the stream. Parts of Shamir shares are assembled into their shares first, which are then
+/// combined into the payload.
+fn assemble_text(input: &str) -> Result<(u8, Vec<u8>)> {
+    let mut stream_version = 0;
+    let mut part_list = Vec::<StreamPart>::new();
     for line in input.lines() {
         // magic check
         if !line.starts_with(QRSTREAM_MAGIC) {
@@ -74,18 +255,33 @@ fn assemble_text(input: &str) -> Result<String> {
         }
         let mut colpos = line.find(';').ok_or_else(err_invalid_input)?;
         // version check
-        let version_s = &line[QRSTREAM_MAGIC.len() + 1..colpos];
-        match version_s.parse::<u8>() {
+        let version_s = line[..colpos]
+            .strip_prefix(QRSTREAM_MAGIC)
+            .and_then(|version_s| version_s.strip_prefix('/'))
+            .ok_or_else(|| err_value_validation("invalid version str"))?;
+        let version = match version_s.parse::<u8>() {
             Ok(version) => {
                 if version > QRSTREAM_VERSION {
                     Err(err_value_validation("unsupported version"))?;
                 }
+                version
             }
             Err(_) => Err(err_value_validation("invalid version str"))?,
         };
+        if stream_version == 0 {
+            stream_version = version;
+        } else if stream_version != version {
+            Err(err_value_validation("inconsistent version"))?;
+        }
         // parts & text
         let mut data_text: Option<&str> = None;
+        let mut base45 = false;
+        let mut checksum: Option<u16> = None;
+        let mut parity = 0;
+        let mut fountain = false;
         let mut this_part = 0;
+        let mut total_parts = 0;
+        let mut share = None;
         while colpos < line.len() - 1 {
             let section_start = colpos + 1;
             let next_colpos = line[section_start..]
@@ -95,20 +291,40 @@ fn assemble_text(input: &str) -> Result<String> {
             let section = &line[section_start..next_colpos];
             if let Some(section) = section.strip_prefix("t=") {
                 data_text = Some(section);
+            } else if let Some(section) = section
+                .strip_prefix("a=")
+                .filter(|_| version != QRSTREAM_VERSION_V1)
+            {
+                data_text = Some(section);
+                base45 = true;
             } else if let Some(section) = section.strip_prefix("p=") {
-                let p_u8 = u8::from_str_radix(section, 16)
-                    .map_err(|_| err_value_validation("invalid part information"))?;
-                this_part = (p_u8 >> 4) - 1;
-                let total_parts = p_u8 & 0x0f;
-                if num_parts == 0 {
-                    num_parts = total_parts as usize;
-                } else if num_parts != total_parts as usize {
-                    Err(err_value_validation("inconsistent number of parts"))?;
-                }
+                (this_part, total_parts) = parse_part_info(section, version)?;
+            } else if let Some(section) = section.strip_prefix("f=") {
+                (this_part, total_parts) = parse_symbol_info(section, version)?;
+                fountain = true;
+            } else if let Some(section) = section.strip_prefix("s=") {
+                share = Some(parse_share_info(section, version)?);
+            } else if let Some(section) = section.strip_prefix("r=") {
+                parity = usize::from_str_radix(section, 16)
+                    .map_err(|_| err_value_validation("invalid parity information"))?;
+            } else if let Some(section) = section.strip_prefix("c=") {
+                checksum = Some(
+                    u16::from_str_radix(section, 16)
+                        .map_err(|_| err_value_validation("invalid part checksum"))?,
+                );
             }
             colpos = next_colpos;
         }
-        part_list.push((this_part, data_text.ok_or_else(err_invalid_input)?));
+        part_list.push(StreamPart {
+            index: this_part,
+            total: total_parts,
+            fountain,
+            parity,
+            checksum,
+            text: data_text.ok_or_else(err_invalid_input)?,
+            base45,
+            share,
+        });
     }
 
     // sanity check
@@ -116,21 +332,213 @@ fn assemble_text(input: &str) -> Result<String> {
         Err(err_invalid_input())?;
     }
 
+    let is_shared = part_list[0].share.is_some();
+    if part_list.iter().any(|p| p.share.is_some() != is_shared) {
+        Err(err_value_validation("inconsistent part types"))?;
+    }
+    if is_shared {
+        assemble_shares(stream_version, part_list).map(|data| (stream_version, data))
+    } else {
+        assemble_stream(stream_version, part_list).map(|data| (stream_version, data))
+    }
+}
+
+/// Assembles the parts of a single stream, which have to agree on how it has been split.
+fn assemble_stream(version: u8, part_list: Vec<StreamPart>) -> Result<Vec<u8>> {
+    let first = &part_list[0];
+    for part in &part_list[1..] {
+        if part.fountain != first.fountain {
+            Err(err_value_validation("inconsistent part types"))?;
+        } else if part.total != first.total {
+            Err(err_value_validation("inconsistent number of parts"))?;
+        } else if part.parity != first.parity {
+            Err(err_value_validation("inconsistent parity information"))?;
+        }
+    }
+
+    if version == QRSTREAM_VERSION_V1 {
+        assemble_v1_parts(part_list)
+    } else if first.fountain {
+        assemble_fountain_parts(part_list)
+    } else {
+        assemble_parts(part_list)
+    }
+}
+
+/// Assembles the parts of each Shamir share into the share, and combines the shares back into
+/// the payload. Shares that can't be assembled are skipped, as long as there are enough others.
+fn assemble_shares(version: u8, part_list: Vec<StreamPart>) -> Result<Vec<u8>> {
+    let threshold = part_list[0].share.map(|(_, t)| t).unwrap_or_default();
+    let mut share_parts = BTreeMap::<u8, Vec<StreamPart>>::new();
+    for part in part_list {
+        let (x, part_threshold) = part.share.ok_or_else(err_invalid_input)?;
+        if part_threshold != threshold {
+            Err(err_value_validation("inconsistent share threshold"))?;
+        }
+        share_parts.entry(x).or_default().push(part);
+    }
+    if share_parts.len() < threshold {
+        Err(err_value_validation(format!(
+            "not enough shares: {} of {threshold} needed",
+            share_parts.len()
+        )))?;
+    }
+
+    let mut shares = Vec::<(u8, Vec<u8>)>::new();
+    let mut first_error = None;
+    for (x, parts) in share_parts {
+        match assemble_stream(version, parts) {
+            Ok(share) => shares.push((x, share)),
+            Err(e) => _ = first_error.get_or_insert(e),
+        }
+    }
+    match first_error {
+        Some(e) if shares.len() < threshold => Err(e),
+        _ => Ok(shamir::combine(&shares)),
+    }
+}
+
+/// Combines the parts of a v1 stream, which split the encoded text of the payload.
+fn assemble_v1_parts(mut part_list: Vec<StreamPart>) -> Result<Vec<u8>> {
     // sort parts
-    part_list.sort_by(|a, b| a.0.cmp(&b.0));
+    part_list.sort_by_key(|p| p.index);
 
     // combine parts and return
+    let num_parts = part_list[0].total;
     let mut combined_text = String::new();
     for i in 0..num_parts {
         match part_list.get(i) {
-            Some((idx, txt)) => {
-                if *idx != i as u8 {
+            Some(part) => {
+                if part.index != i {
                     Err(err_invalid_input())?;
                 }
-                combined_text += txt;
+                combined_text += part.text;
             }
             None => Err(err_value_validation("incomplete list of input"))?,
         }
     }
-    Ok(combined_text)
+    Ok(URL_SAFE_NO_PAD
+        .decode(combined_text)
+        .map_err(|_| err_invalid_input())?)
+}
+
+/// Combines the parts of a v2 stream, each of which carries a shard of the payload. Parts
+/// that are missing or corrupt are rebuilt from the parity parts, if there are enough of them.
+fn assemble_parts(part_list: Vec<StreamPart>) -> Result<Vec<u8>> {
+    let num_parts = part_list[0].total;
+    let parity = part_list[0].parity;
+    // Reed-Solomon works with at most 256 shards, data and parity together
+    if parity >= num_parts || (parity > 0 && num_parts > MAX_SHARDS) {
+        Err(err_value_validation("invalid parity information"))?;
+    }
+    // the total comes from the scanned text, so it's checked against the parts actually
+    // received before anything is allocated for it
+    if num_parts - parity > part_list.len() {
+        Err(err_value_validation("incomplete list of input"))?;
+    }
+    let mut shards = vec![None; num_parts];
+    let mut corrupt_parts = Vec::<usize>::new();
+    for part in part_list {
+        let shard = part.data();
+        if shard.is_none() {
+            corrupt_parts.push(part.index);
+        } else if shards[part.index].is_none() {
+            shards[part.index] = shard;
+        }
+    }
+
+    let missing = shards.iter().filter(|s| s.is_none()).count();
+    if missing > parity {
+        if !corrupt_parts.is_empty() {
+            corrupt_parts.sort();
+            corrupt_parts.dedup();
+            let corrupt_parts = corrupt_parts
+                .iter()
+                .map(|p| format!("{} of {}", p + 1, num_parts))
+                .collect::<Vec<_>>();
+            Err(err_value_validation(format!(
+                "corrupt part: {}",
+                corrupt_parts.join(", ")
+            )))?;
+        }
+        Err(err_value_validation("incomplete list of input"))?;
+    } else if missing > 0 {
+        let rs = ReedSolomon::new(num_parts - parity, parity)
+            .map_err(|_| err_value_validation("invalid parity information"))?;
+        rs.reconstruct_data(&mut shards)
+            .map_err(|_| err_value_validation("unable to recover missing parts"))?;
+    }
+    Ok(shards
+        .into_iter()
+        .take(num_parts - parity)
+        .flat_map(|s| s.unwrap_or_default())
+        .collect())
+}
+
+/// Combines the fountain coded parts of a v2 stream, which may come in any order, and with
+/// duplicates.
+fn assemble_fountain_parts(part_list: Vec<StreamPart>) -> Result<Vec<u8>> {
+    let num_blocks = part_list[0].total;
+    let symbols = part_list
+        .into_iter()
+        .filter_map(|part| Some((part.index + 1, part.data()?)))
+        .collect::<Vec<_>>();
+    if symbols.iter().any(|(_, s)| s.len() != symbols[0].1.len()) {
+        Err(err_value_validation("inconsistent fountain code length"))?;
+    }
+    let blocks = fountain::decode(num_blocks, symbols)
+        .ok_or_else(|| err_value_validation("incomplete list of input"))?;
+    Ok(blocks.concat())
+}
+
+/// Parses the `f=` section of a fountain coded part, returning the zero based symbol index and
+/// the number of blocks the payload was split into.
+fn parse_symbol_info(section: &str, version: u8) -> Result<(usize, usize)> {
+    let invalid_symbol_info = || err_value_validation("invalid fountain code information");
+    if version == QRSTREAM_VERSION_V1 {
+        Err(invalid_symbol_info())?;
+    }
+    let (id_s, blocks_s) = section.split_once('/').ok_or_else(invalid_symbol_info)?;
+    let id = usize::from_str_radix(id_s, 16).map_err(|_| invalid_symbol_info())?;
+    let blocks = usize::from_str_radix(blocks_s, 16).map_err(|_| invalid_symbol_info())?;
+    if id == 0 || blocks == 0 || blocks > fountain::MAX_BLOCKS {
+        Err(invalid_symbol_info())?;
+    }
+    Ok((id - 1, blocks))
+}
+
+/// Parses the `s=` section of a part of a Shamir share, returning the x coordinate of the share
+/// and the number of shares needed to decode.
+fn parse_share_info(section: &str, version: u8) -> Result<(u8, usize)> {
+    let invalid_share_info = || err_value_validation("invalid share information");
+    if version == QRSTREAM_VERSION_V1 {
+        Err(invalid_share_info())?;
+    }
+    let (x_s, threshold_s) = section.split_once('/').ok_or_else(invalid_share_info)?;
+    let x = u8::from_str_radix(x_s, 16).map_err(|_| invalid_share_info())?;
+    let threshold = usize::from_str_radix(threshold_s, 16).map_err(|_| invalid_share_info())?;
+    if x == 0 || threshold < 2 {
+        Err(invalid_share_info())?;
+    }
+    Ok((x, threshold))
+}
+
+/// Parses the `p=` section of a part, returning the zero based part index and the total
+/// number of parts.
+fn parse_part_info(section: &str, version: u8) -> Result<(usize, usize)> {
+    let invalid_part_info = || err_value_validation("invalid part information");
+    let (part, total) = if version == QRSTREAM_VERSION_V1 {
+        let p_u8 = u8::from_str_radix(section, 16).map_err(|_| invalid_part_info())?;
+        ((p_u8 >> 4) as usize, (p_u8 & 0x0f) as usize)
+    } else {
+        let (part_s, total_s) = section.split_once('/').ok_or_else(invalid_part_info)?;
+        (
+            usize::from_str_radix(part_s, 16).map_err(|_| invalid_part_info())?,
+            usize::from_str_radix(total_s, 16).map_err(|_| invalid_part_info())?,
+        )
+    };
+    if part == 0 || part > total {
+        Err(invalid_part_info())?;
+    }
+    Ok((part - 1, total))
 }
@@ -1,148 +1,435 @@
-use aes_gcm::{
-    aead::{
-        rand_core::{OsRng, RngCore},
-        Aead, AeadCore, KeyInit,
-    },
-    Aes256Gcm, Key,
-};
-use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
-use image::{GenericImage, GrayImage, ImageBuffer, Luma};
+use aes_gcm::aead::rand_core::{OsRng, RngCore};
+use ed25519_dalek::Signer;
+use image::{imageops, GrayImage};
+use qr_code::bits::{self, Bits};
 use qr_code::{EcLevel, QrCode};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use sha2::{Digest, Sha256};
 use std::io::{Cursor, Write};
 
 use crate::{
-    error::{io_error, usage_err},
-    OutputFormat, QRSTREAM_MAGIC, QRSTREAM_VERSION,
+    animation, base45,
+    error::{err_value_validation, io_error, usage_err},
+    fountain,
+    header::StreamHeader,
+    kdf::{key_check, Kdf, SALT_LEN},
+    keyslot::{KeySlot, MAX_KEY_SLOTS},
+    label::{self, LABEL_HEIGHT},
+    memory::{SecretKey, SecretVec},
+    metadata::Metadata,
+    part_checksum, pdf,
+    recipient::MAX_RECIPIENTS,
+    shamir, signature, structured_append, terminal, EncodeOptions, InputSource, OutputFormat,
+    QRSTREAM_MAGIC, QRSTREAM_VERSION,
 };
 
 use super::error::{Error, Result};
 use super::QRStreamOptions;
 
 pub(crate) async fn encode(options: &QRStreamOptions) -> Result<()> {
-    let input = options.input.get_content().await?;
-    let result_list = encode_data(&input, options)?;
+    let [input_source] = options.input.as_slice() else {
+        Err(err_value_validation("only one input can be encoded"))?
+    };
+    let input = input_source.get_content().await?;
+    let payload = encode_payload(&input, input_source, options)?;
 
     let encode_options = options.encode_options();
+    let sheets = if let (Some(count), Some(threshold)) =
+        (encode_options.shares, encode_options.threshold)
+    {
+        if threshold < 2 || threshold > count || count > shamir::MAX_SHARES {
+            Err(err_value_validation(format!(
+                "invalid shares, the threshold must be at least 2, and at most the number of shares, which must be at most {}",
+                shamir::MAX_SHARES
+            )))?;
+        }
+        if !encode_options.out_format.has_sheets() {
+            Err(err_value_validation(
+                "shares are written one sheet each, use the txt, png, svg or pdf output format",
+            ))?;
+        }
+        // each share gets a sheet of its own, to be handed to a different custodian. The
+        // sheets are identical once printed, save for this label
+        shamir::split(&payload, count, threshold)
+            .into_iter()
+            .map(|(x, share)| {
+                Ok(Sheet {
+                    label: Some(format!("share {x} of {count}, {threshold} needed")),
+                    codes: encode_parts(&share, &format!("s={x:x}/{threshold:x};"), options)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        vec![Sheet {
+            label: None,
+            codes: encode_parts(&payload, "", options)?,
+        }]
+    };
+    write_output(&sheets, encode_options, &mut std::io::stdout())?;
+
+    Ok(())
+}
+
+/// Codes printed together, e.g. those of a share, with `label` written above them.
+pub(crate) struct Sheet {
+    pub(crate) label: Option<String>,
+    pub(crate) codes: Vec<(String, QrCode)>,
+}
+
+/// Writes the codes in the output format asked for, as a single document. Formats without
+/// sheets only ever get one.
+fn write_output(
+    sheets: &[Sheet],
+    encode_options: &EncodeOptions,
+    out: &mut impl Write,
+) -> Result<()> {
+    let codes = &sheets[0].codes;
     match encode_options.out_format {
         OutputFormat::Txt => {
-            for (output, _) in &result_list {
-                println!("{}", output);
+            for (output, _) in sheets.iter().flat_map(|sheet| &sheet.codes) {
+                writeln!(out, "{}", output)?;
             }
         }
-        OutputFormat::Png => write_as_png(&result_list, encode_options.qr_per_row)?,
+        OutputFormat::Apng => animation::write_as_apng(codes, encode_options.frame_rate, out)?,
+        OutputFormat::Gif => animation::write_as_gif(codes, encode_options.frame_rate, out)?,
+        OutputFormat::Pdf => pdf::write_as_pdf(sheets, encode_options, out)?,
+        OutputFormat::Png => write_as_png(sheets, encode_options.qr_per_row, out)?,
+        OutputFormat::Term => terminal::write_as_term(codes, encode_options, out)?,
+        OutputFormat::Svg => write_as_svg(
+            sheets,
+            encode_options.qr_per_row,
+            encode_options.captions,
+            out,
+        )?,
     }
-
     Ok(())
 }
 
-fn encode_data(u8_data: &[u8], options: &QRStreamOptions) -> Result<Vec<(String, QrCode)>> {
-    let bin_data = if let Some(key) = &options.key {
-        let key = Key::<Aes256Gcm>::from_slice(key);
-        let cipher = Aes256Gcm::new(key);
-        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
-        let mut enc_data = Vec::<u8>::new();
-        let nonce_len = ((OsRng.next_u32() & 0xe0) as u8) | (nonce.len() as u8 & 0x1f);
-        enc_data.push(nonce_len);
-        enc_data.extend_from_slice(nonce.as_slice());
-        enc_data.extend(cipher.encrypt(&nonce, u8_data)?);
-        enc_data
+/// Builds the payload for `u8_data`, i.e. the stream header followed by the data, compressed
+/// and encrypted as asked for.
+fn encode_payload(
+    u8_data: &[u8],
+    input_source: &InputSource,
+    options: &QRStreamOptions,
+) -> Result<Vec<u8>> {
+    let mut header = StreamHeader::default();
+    let metadata = if options.encode_options().metadata {
+        header.metadata = true;
+        Metadata::collect(input_source, u8_data)?.to_bytes()
     } else {
+        Vec::new()
+    };
+    let mut plain_data = SecretVec::with_capacity(metadata.len() + u8_data.len());
+    plain_data.extend_from_slice(&metadata);
+    plain_data.extend_from_slice(u8_data);
+    let (compression, u8_data) = options.encode_options().compress.compress(&plain_data)?;
+    header.compression = compression;
+    let u8_data = &*u8_data;
+    let recipients = &options.encode_options().recipients;
+    let cipher = options.encode_options().cipher;
+    let key = match (options.secrets.as_slice(), recipients.as_slice()) {
+        ([], []) => None,
+        // a single password derives the key of the payload directly
+        ([secret], []) => {
+            if secret.is_password() {
+                let mut salt = vec![0u8; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                header.kdf = Some(options.encode_options().kdf.clone());
+                header.salt = salt;
+            }
+            Some(secret.derive_key(header.kdf.as_ref().unwrap_or(&Kdf::LEGACY), &header.salt)?)
+        }
+        // otherwise the key is random, and wrapped for each password and recipient
+        (secrets, recipients) => {
+            if secrets.len() > MAX_KEY_SLOTS || recipients.len() > MAX_RECIPIENTS {
+                Err(err_value_validation(format!(
+                    "too many passwords or recipients, at most {MAX_KEY_SLOTS} and {MAX_RECIPIENTS} are allowed"
+                )))?;
+            }
+            let mut key = SecretKey::new();
+            OsRng.fill_bytes(key.as_mut_slice());
+            let kdf = &options.encode_options().kdf;
+            header.key_slots = secrets
+                .iter()
+                .map(|secret| KeySlot::wrap(secret, kdf, cipher, &key))
+                .collect::<Result<_>>()?;
+            header.recipients = recipients
+                .iter()
+                .map(|recipient| recipient.wrap(&key))
+                .collect::<Result<_>>()?;
+            Some(key)
+        }
+    };
+    let body = if let Some(key) = key {
+        header.cipher = cipher;
+        header.key_check = key_check(&key);
+        header.length = header.cipher.encrypted_len(u8_data.len()) as u32;
+        let aad = header.associated_data();
+        header.cipher.encrypt(&key, u8_data, &aad)?
+    } else {
+        header.length = u8_data.len() as u32;
         u8_data.to_vec()
     };
-    let data = URL_SAFE_NO_PAD.encode(bin_data);
+    header.digest = Sha256::digest(&body).to_vec();
+    if let Some(path) = &options.encode_options().sign_key {
+        let signing_key = signature::read_signing_key(path)?;
+        header.signature = signing_key.sign(&header.signed_data(&body)).to_vec();
+    }
+    let mut payload = header.to_bytes();
+    payload.extend(body);
+    Ok(payload)
+}
 
+/// Splits `payload` into as many parts as needed for each to fit in a QR code, returning the
+/// line of each part along with its QR code. `stream_info` is added to each line, before the
+/// sections telling where the part fits in the stream.
+fn encode_parts(
+    payload: &[u8],
+    stream_info: &str,
+    options: &QRStreamOptions,
+) -> Result<Vec<(String, QrCode)>> {
+    // each try encodes the whole payload, so the number of parts is searched for from a lower
+    // bound, i.e. parts filling version 40 codes, in steps that double until the parts fit, and
+    // then by bisection. Tries that hit a limit count as enough parts, as more would too
+    let max_part_len = max_alphanumeric_len(options.encode_options().ec_level) * 2 / 3;
+    let try_parts = |parts_needed| encode_parts_as(payload, stream_info, options, parts_needed);
+    let mut too_few = payload.len().div_ceil(max_part_len).max(1) - 1;
+    let mut step = 1;
+    let (mut enough, mut result) = loop {
+        let parts_needed = (too_few + step).min(payload.len());
+        if parts_needed == too_few {
+            usage_err("data too large to encode");
+        }
+        match try_parts(parts_needed) {
+            Ok(None) => (too_few, step) = (parts_needed, step * 2),
+            result => break (parts_needed, result),
+        }
+    };
+    while enough - too_few > 1 {
+        let parts_needed = too_few + (enough - too_few) / 2;
+        match try_parts(parts_needed) {
+            Ok(None) => too_few = parts_needed,
+            attempt => (enough, result) = (parts_needed, attempt),
+        }
+    }
     let ec_level = options.encode_options().ec_level;
-    let mut parts_needed = 1;
-    while parts_needed < 16 {
-        let part_len = (data.len() + parts_needed - 1) / parts_needed;
-        let first_part = &data[..part_len];
-        match encode_to_qr(first_part, 0, parts_needed as u8, ec_level) {
-            Ok((output, qr)) => {
-                let mut result_list = Vec::<(String, QrCode)>::new();
-                result_list.push((output, qr));
-                for current_part in 1..parts_needed {
-                    let idx1 = current_part * part_len;
-                    let idx2 = std::cmp::min((current_part + 1) * part_len, data.len());
-                    let part = &data[idx1..idx2];
-                    let (output, qr) =
-                        encode_to_qr(part, current_part as u8, parts_needed as u8, ec_level)?;
-                    result_list.push((output, qr));
-                }
-                return Ok(result_list);
-            }
-            Err(Error::Qr(qr_code::types::QrError::DataTooLong)) => {
-                parts_needed += 1;
-                continue;
+    result?
+        .expect("only tries with parts that fit are kept")
+        .into_iter()
+        .map(|(line, bits)| Ok((line, QrCode::with_bits(bits, ec_level)?)))
+        .collect()
+}
+
+/// Splits `payload` into `parts_needed` parts, as [encode_parts] does, returning `None` if they
+/// don't fit in QR codes. Only the bits of the codes are computed, as they tell whether the
+/// parts fit, and drawing the codes takes much longer.
+fn encode_parts_as(
+    payload: &[u8],
+    stream_info: &str,
+    options: &QRStreamOptions,
+    parts_needed: usize,
+) -> Result<Option<Vec<(String, Bits)>>> {
+    let ec_level = options.encode_options().ec_level;
+    let parity = options.encode_options().parity;
+    let fountain = options.encode_options().fountain;
+    let structured_append = options.encode_options().structured_append;
+    let parts = match fountain {
+        Some(count) => {
+            let blocks = split_into_shards(payload, parts_needed, 0)?;
+            let k = blocks.len();
+            if k > fountain::MAX_BLOCKS {
+                Err(err_value_validation(format!(
+                    "data too large for fountain coding, which allows at most {} blocks",
+                    fountain::MAX_BLOCKS
+                )))?;
             }
+            // at least `k` symbols are generated, as whether `count` is too few can only be
+            // told once the symbols are known to fit in a QR code
+            fountain::encode(&blocks, count.max(k))
+                .into_iter()
+                .enumerate()
+                .map(|(idx, symbol)| (format!("f={:x}/{:x};", idx + 1, k), symbol))
+                .collect::<Vec<_>>()
+        }
+        None => {
+            let shards = split_into_shards(payload, parts_needed, parity)?;
+            let total = shards.len();
+            shards
+                .into_iter()
+                .enumerate()
+                .map(|(idx, shard)| {
+                    let mut info = format!("p={:x}/{:x};", idx + 1, total);
+                    if parity > 0 {
+                        info += format!("r={:x};", parity).as_str();
+                    }
+                    (info, shard)
+                })
+                .collect::<Vec<_>>()
+        }
+    };
+    let lines = parts
+        .iter()
+        .map(|(part_info, part)| {
+            part_line(&base45::encode(part), &format!("{stream_info}{part_info}"))
+        })
+        .collect::<Vec<_>>();
+    if structured_append && lines.len() > structured_append::MAX_CODES {
+        Err(err_value_validation(format!(
+            "data too large for structured append, which allows at most {} codes",
+            structured_append::MAX_CODES
+        )))?;
+    }
+    // with structured append, each code ends with a newline, so that the data of the set
+    // is the same as the txt output
+    let contents = lines
+        .iter()
+        .map(|line| format!("{line}\n"))
+        .collect::<Vec<_>>();
+    let sa_parity = structured_append::parity(contents.iter().map(|c| c.as_bytes()));
+    let mut result_list = Vec::<(String, Bits)>::new();
+    for (idx, (line, content)) in lines.into_iter().zip(&contents).enumerate() {
+        let bits = if structured_append {
+            structured_append::encode(content.as_bytes(), idx, contents.len(), sa_parity, ec_level)
+        } else {
+            bits::encode_auto(line.as_bytes(), ec_level).map_err(Error::from)
+        };
+        match bits {
+            Ok(bits) => result_list.push((line, bits)),
+            Err(Error::Qr(qr_code::types::QrError::DataTooLong)) => return Ok(None),
             Err(err) => {
                 return Err(err);
             }
-        };
+        }
+    }
+    if let Some(count) = fountain.filter(|count| *count < result_list.len()) {
+        Err(err_value_validation(format!(
+            "too few fountain codes ({count}), at least {} needed",
+            result_list.len()
+        )))?;
+    }
+    Ok(Some(result_list))
+}
+
+/// Number of alphanumeric characters a version 40 code holds at `ec_level`, i.e. the most any
+/// code holds.
+fn max_alphanumeric_len(ec_level: EcLevel) -> usize {
+    match ec_level {
+        EcLevel::L => 4296,
+        EcLevel::M => 3391,
+        EcLevel::Q => 2420,
+        EcLevel::H => 1852,
     }
-    usage_err("data too large to encode")
 }
 
-fn encode_to_qr(data: &str, part: u8, total: u8, level: EcLevel) -> Result<(String, QrCode)> {
+/// Splits `payload` into up to `data_parts` shards of equal length, zero padding the last one,
+/// followed by `parity` Reed-Solomon parity shards. Any `parity` of the shards can then be
+/// lost, and the payload still be recovered.
+fn split_into_shards(payload: &[u8], data_parts: usize, parity: usize) -> Result<Vec<Vec<u8>>> {
+    let shard_len = payload.len().div_ceil(data_parts);
+    let mut shards = payload
+        .chunks(shard_len)
+        .map(|chunk| {
+            let mut shard = chunk.to_vec();
+            shard.resize(shard_len, 0);
+            shard
+        })
+        .collect::<Vec<_>>();
+    if parity > 0 {
+        let rs = ReedSolomon::new(shards.len(), parity)
+            .map_err(|e| err_value_validation(format!("cannot add parity: {e}")))?;
+        shards.resize(shards.len() + parity, vec![0; shard_len]);
+        rs.encode(&mut shards)
+            .map_err(|e| err_value_validation(format!("cannot add parity: {e}")))?;
+    }
+    Ok(shards)
+}
+
+/// Returns the line for a part with base45 `data`, with `part_info` being the sections that tell
+/// where it fits in the stream. The base45 text ends up in an alphanumeric segment of the QR code.
+fn part_line(data: &str, part_info: &str) -> String {
     let mut output = String::new();
     output += format!("{}/{};", QRSTREAM_MAGIC, QRSTREAM_VERSION).as_str();
-    output += format!("p={:x};", ((part + 1) << 4) | (total & 0x0f)).as_str();
-    output += "t=";
+    output += part_info;
+    output += format!("c={:04x};", part_checksum(data)).as_str();
+    output += "a=";
     output += data;
+    output
+}
+
+/// Modules of blank space around each code, as required by the QR spec
+pub(crate) const QUIET_ZONE: usize = 4;
+
+/// Spacing around and between the codes of a grid, in pixels
+const GRID_SPACING: u32 = 64;
 
-    let output_bytes = output.as_bytes();
-    let qr = QrCode::with_error_correction_level(output_bytes, level)?;
-    Ok((output, qr))
+/// Where a code goes in a grid, and the size of its modules, in pixels
+struct GridCell {
+    x: u32,
+    y: u32,
+    pixel_per_mod: u32,
 }
 
-fn write_as_png(qr_vec: &[(String, QrCode)], codes_per_row: u32) -> Result<()> {
-    let spacing = 64;
-    let mut img_vec = Vec::<ImageBuffer<Luma<u8>, Vec<u8>>>::new();
-    let mut img_width = 0;
-    let mut img_height = spacing;
+/// Lays `qr_vec` out in rows of `codes_per_row`, returning the width and height of the grid,
+/// along with the cell of each code.
+fn grid_layout(qr_vec: &[(String, QrCode)], codes_per_row: u32) -> (u32, u32, Vec<GridCell>) {
+    let spacing = GRID_SPACING;
+    let mut cells = Vec::<GridCell>::new();
+    let mut width = 0;
+    let mut height = spacing;
+    let mut x = spacing;
+    let mut y = spacing;
     for (idx, (_, qr)) in qr_vec.iter().enumerate() {
         let qr_size = qr.width() as u32;
         let pixel_per_mod = std::cmp::max(4, (360f32 / qr_size as f32).floor() as u32); // technically, sqrt
         let qr_img_size = qr_size * pixel_per_mod;
-        img_width = std::cmp::max(
-            img_width,
+        width = std::cmp::max(
+            width,
             qr_img_size * codes_per_row + spacing * (codes_per_row + 1),
         );
         if idx % codes_per_row as usize == 0 {
-            img_height += qr_img_size + spacing;
+            height += qr_img_size + spacing;
         }
-        let mut img = GrayImage::new(qr_img_size, qr_img_size);
-        qr.to_vec()
-            .chunks(qr_size as usize)
-            .enumerate()
-            .for_each(|(y, row)| {
-                row.iter().enumerate().for_each(|(x, val)| {
-                    let val = if *val { 0 } else { 255 };
-                    for dy in 0..pixel_per_mod {
-                        for dx in 0..pixel_per_mod {
-                            img.put_pixel(
-                                x as u32 * pixel_per_mod + dx,
-                                y as u32 * pixel_per_mod + dy,
-                                image::Luma([val]),
-                            );
-                        }
-                    }
-                });
-            });
-        img_vec.push(img);
-    }
-    let mut img = GrayImage::new(img_width, img_height);
-    img.fill(255);
-    let mut x = spacing;
-    let mut y = spacing;
-    for (idx, qr_img) in img_vec.iter().enumerate() {
-        img.copy_from(qr_img, x, y).unwrap();
+        cells.push(GridCell {
+            x,
+            y,
+            pixel_per_mod,
+        });
         if idx % codes_per_row as usize != codes_per_row as usize - 1 {
-            x += qr_img.width() + spacing;
+            x += qr_img_size + spacing;
         } else {
             x = spacing;
-            y += qr_img.height() + spacing;
+            y += qr_img_size + spacing;
         }
     }
+    (width, height, cells)
+}
+
+/// Writes the sheets as a PNG, one below the other, each a grid drawn by [render_grid] with its
+/// label above it.
+pub(crate) fn write_as_png(
+    sheets: &[Sheet],
+    codes_per_row: u32,
+    out: &mut impl Write,
+) -> Result<()> {
+    let images = sheets
+        .iter()
+        .map(|sheet| {
+            let img = render_grid(&sheet.codes, codes_per_row);
+            match &sheet.label {
+                Some(label) => label::with_label(&img, label, GRID_SPACING),
+                None => img,
+            }
+        })
+        .collect::<Vec<_>>();
+    let width = images.iter().map(|img| img.width()).max().unwrap_or(0);
+    let height = images.iter().map(|img| img.height()).sum();
+    let mut img = GrayImage::new(width, height);
+    img.fill(255);
+    let mut top = 0;
+    for sheet_img in &images {
+        imageops::replace(&mut img, sheet_img, 0, top.into());
+        top += sheet_img.height();
+    }
     let mut png_data = Vec::<u8>::new();
     let mut writer = Cursor::new(&mut png_data);
     image::write_buffer_with_format(
@@ -154,6 +441,122 @@ fn write_as_png(qr_vec: &[(String, QrCode)], codes_per_row: u32) -> Result<()> {
         image::ImageFormat::Png,
     )
     .map_err(|e| io_error(e.to_string()))?;
-    std::io::stdout().write_all(&png_data)?;
+    out.write_all(&png_data)?;
+    Ok(())
+}
+
+/// Draws `qr_vec` in a grid of `codes_per_row` columns, black on white.
+pub(crate) fn render_grid(qr_vec: &[(String, QrCode)], codes_per_row: u32) -> GrayImage {
+    let (width, height, cells) = grid_layout(qr_vec, codes_per_row);
+    let mut img = GrayImage::new(width, height);
+    img.fill(255);
+    for ((_, qr), cell) in qr_vec.iter().zip(&cells) {
+        let qr_size = qr.width();
+        qr.to_vec()
+            .chunks(qr_size)
+            .enumerate()
+            .for_each(|(y, row)| {
+                row.iter().enumerate().for_each(|(x, val)| {
+                    let val = if *val { 0 } else { 255 };
+                    for dy in 0..cell.pixel_per_mod {
+                        for dx in 0..cell.pixel_per_mod {
+                            img.put_pixel(
+                                cell.x + x as u32 * cell.pixel_per_mod + dx,
+                                cell.y + y as u32 * cell.pixel_per_mod + dy,
+                                image::Luma([val]),
+                            );
+                        }
+                    }
+                });
+            });
+    }
+    img
+}
+
+/// Writes the same sheets as [write_as_png] as an SVG document, with each row of modules drawn
+/// as rectangles, so that it prints crisp at any resolution. With `captions`, the part number of
+/// each code is written below it.
+fn write_as_svg(
+    sheets: &[Sheet],
+    codes_per_row: u32,
+    captions: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    let layouts = sheets
+        .iter()
+        .map(|sheet| grid_layout(&sheet.codes, codes_per_row))
+        .collect::<Vec<_>>();
+    // labels get a band of their own, as in the PNG output
+    let label_height = |sheet: &Sheet| sheet.label.as_ref().map_or(0, |_| LABEL_HEIGHT);
+    let width = layouts
+        .iter()
+        .map(|(width, _, _)| *width)
+        .max()
+        .unwrap_or(0);
+    let height = sheets
+        .iter()
+        .zip(&layouts)
+        .map(|(sheet, (_, height, _))| label_height(sheet) + height)
+        .sum::<u32>();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" shape-rendering="crispEdges">"#
+    )?;
+    writeln!(
+        out,
+        r##"<rect width="{width}" height="{height}" fill="#fff"/>"##
+    )?;
+    let mut sheet_top = 0;
+    for (sheet, (_, sheet_height, cells)) in sheets.iter().zip(&layouts) {
+        if let Some(label) = &sheet.label {
+            writeln!(
+                out,
+                r#"<text x="{GRID_SPACING}" y="{}" font-family="sans-serif" font-size="24">{label}</text>"#,
+                sheet_top + LABEL_HEIGHT * 2 / 3
+            )?;
+        }
+        let top = sheet_top + label_height(sheet);
+        writeln!(out, r##"<g fill="#000">"##)?;
+        for ((_, qr), cell) in sheet.codes.iter().zip(cells) {
+            let qr_size = qr.width();
+            for (y, row) in qr.to_vec().chunks(qr_size).enumerate() {
+                // adjacent dark modules are joined into a single rectangle
+                let mut x = 0;
+                while x < qr_size {
+                    if !row[x] {
+                        x += 1;
+                        continue;
+                    }
+                    let run = row[x..].iter().take_while(|val| **val).count();
+                    writeln!(
+                        out,
+                        r#"<rect x="{}" y="{}" width="{}" height="{}"/>"#,
+                        cell.x + x as u32 * cell.pixel_per_mod,
+                        top + cell.y + y as u32 * cell.pixel_per_mod,
+                        run as u32 * cell.pixel_per_mod,
+                        cell.pixel_per_mod
+                    )?;
+                    x += run;
+                }
+            }
+        }
+        writeln!(out, "</g>")?;
+        if captions {
+            for (idx, ((_, qr), cell)) in sheet.codes.iter().zip(cells).enumerate() {
+                let qr_img_size = qr.width() as u32 * cell.pixel_per_mod;
+                writeln!(
+                    out,
+                    r#"<text x="{}" y="{}" font-family="sans-serif" font-size="24" text-anchor="middle">part {} of {}</text>"#,
+                    cell.x + qr_img_size / 2,
+                    top + cell.y + qr_img_size + GRID_SPACING * 5 / 8,
+                    idx + 1,
+                    sheet.codes.len()
+                )?;
+            }
+        }
+        sheet_top = top + sheet_height;
+    }
+    writeln!(out, "</svg>")?;
     Ok(())
 }
@@ -1,6 +1,6 @@
 #[derive(Debug)]
 pub enum Error {
-    Io(std::io::Error),
+    Io(#[allow(dead_code)] std::io::Error),
     Arg(clap::Error),
     Crypto(aes_gcm::Error),
     Qr(qr_code::types::QrError),
@@ -33,7 +33,7 @@ impl From<clap::Error> for Error {
 
 impl From<Box<dyn std::error::Error + Send + Sync>> for Error {
     fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
-        Error::Io(std::io::Error::new(std::io::ErrorKind::Other, e))
+        Error::Io(std::io::Error::other(e))
     }
 }
 
@@ -65,7 +65,7 @@ pub(crate) fn err_invalid_input() -> clap::error::Error {
 }
 
 pub(crate) fn io_error(message: impl AsRef<str>) -> std::io::Error {
-    std::io::Error::new(std::io::ErrorKind::Other, message.as_ref())
+    std::io::Error::other(message.as_ref())
 }
 
 pub(crate) trait UnwrapOrExit<T> {
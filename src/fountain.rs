@@ -0,0 +1,136 @@
+//! Fountain (LT) coding of the payload. The payload is split into `k` blocks, and any number
+//! of symbols can be generated from them, each being the XOR of a few blocks. The decoder can
+//! rebuild the payload from any set of symbols that covers all blocks, in whatever order they
+//! arrive.
+//!
+//! Symbols are numbered from 1. Symbols `1..=k` are the blocks themselves, so that a lossless
+//! transfer needs no more than `k` symbols. The blocks making up any other symbol are chosen by
+//! a PRNG seeded with its number, which the camera page mirrors, so it must not change.
+
+/// Maximum number of blocks, as the decoder needs memory in the square of it
+pub(crate) const MAX_BLOCKS: usize = 4096;
+
+/// Returns the zero based indices of the blocks, out of `k`, that make up symbol `id`.
+pub(crate) fn neighbours(id: usize, k: usize) -> Vec<usize> {
+    if id <= k {
+        return vec![id - 1];
+    }
+    let mut rng = Mulberry32(id as u32);
+    let degree = degree(rng.next(), k);
+    let mut blocks = Vec::<usize>::with_capacity(degree);
+    while blocks.len() < degree {
+        let block = rng.next() as usize % k;
+        if !blocks.contains(&block) {
+            blocks.push(block);
+        }
+    }
+    blocks
+}
+
+/// Generates symbols `1..=count` from `blocks`, which must all be of the same length.
+pub(crate) fn encode(blocks: &[Vec<u8>], count: usize) -> Vec<Vec<u8>> {
+    (1..=count)
+        .map(|id| {
+            let mut symbol = vec![0u8; blocks[0].len()];
+            for block in neighbours(id, blocks.len()) {
+                xor_into(&mut symbol, &blocks[block]);
+            }
+            symbol
+        })
+        .collect()
+}
+
+/// Rebuilds the `k` blocks from `symbols`, given as `(id, symbol)` pairs. Returns `None` if
+/// the symbols don't cover all the blocks.
+pub(crate) fn decode(k: usize, symbols: Vec<(usize, Vec<u8>)>) -> Option<Vec<Vec<u8>>> {
+    // `k` comes from the scanned text, so it's checked before the decoder is sized for it
+    if k > MAX_BLOCKS || symbols.len() < k {
+        return None;
+    }
+    let mut decoder = Decoder::new(k);
+    for (id, symbol) in symbols {
+        decoder.add(&neighbours(id, k), symbol);
+    }
+    decoder.finish()
+}
+
+/// Gaussian elimination over GF(2), with each row being a set of blocks and the XOR of their
+/// data. Rows are kept in echelon form as they are added, keyed by their leading block.
+struct Decoder {
+    pivots: Vec<Option<(Vec<bool>, Vec<u8>)>>,
+}
+
+impl Decoder {
+    fn new(k: usize) -> Self {
+        Self {
+            pivots: vec![None; k],
+        }
+    }
+
+    fn add(&mut self, blocks: &[usize], mut data: Vec<u8>) {
+        let mut row = vec![false; self.pivots.len()];
+        blocks.iter().for_each(|b| row[*b] = true);
+        for col in 0..row.len() {
+            if !row[col] {
+                continue;
+            }
+            match &self.pivots[col] {
+                Some((pivot_row, pivot_data)) => {
+                    row.iter_mut().zip(pivot_row).for_each(|(a, b)| *a ^= b);
+                    xor_into(&mut data, pivot_data);
+                }
+                None => {
+                    self.pivots[col] = Some((row, data));
+                    return;
+                }
+            }
+        }
+        // the symbol was redundant, i.e. a duplicate or a combination of earlier ones
+    }
+
+    fn finish(mut self) -> Option<Vec<Vec<u8>>> {
+        if self.pivots.iter().any(|p| p.is_none()) {
+            return None;
+        }
+        // back substitution, starting from the last block, which is already solved
+        let mut blocks = vec![Vec::new(); self.pivots.len()];
+        for col in (0..self.pivots.len()).rev() {
+            let (row, mut data) = self.pivots[col].take()?;
+            for (other, _) in row.iter().enumerate().skip(col + 1).filter(|(_, b)| **b) {
+                xor_into(&mut data, &blocks[other]);
+            }
+            blocks[col] = data;
+        }
+        Some(blocks)
+    }
+}
+
+/// Samples a degree from the ideal soliton distribution over `1..=k`, using integer arithmetic
+/// only, so that the camera page gets the same results.
+fn degree(r: u32, k: usize) -> usize {
+    const RANGE: u64 = 1 << 32;
+    let r = r as u64;
+    let base = RANGE / k as u64;
+    if r < base {
+        return 1;
+    }
+    (2..=k)
+        .find(|d| r < base + RANGE - RANGE / *d as u64)
+        .unwrap_or(k)
+}
+
+fn xor_into(target: &mut [u8], source: &[u8]) {
+    target.iter_mut().zip(source).for_each(|(a, b)| *a ^= b);
+}
+
+/// The mulberry32 PRNG, chosen as it's trivial to replicate in javascript.
+struct Mulberry32(u32);
+
+impl Mulberry32 {
+    fn next(&mut self) -> u32 {
+        self.0 = self.0.wrapping_add(0x6d2b79f5);
+        let mut t = (self.0 ^ (self.0 >> 15)).wrapping_mul(1 | self.0);
+        t = t.wrapping_add((t ^ (t >> 7)).wrapping_mul(61 | t)) ^ t;
+        t ^ (t >> 14)
+    }
+}
@@ -0,0 +1,192 @@
+//! Header of v2 streams. It sits at the start of the assembled payload, and describes how the
+//! rest of the payload has to be processed to get back the original data.
+//!
+//! The header is serialized as a big-endian `u16` length, followed by that many bytes of
+//! entries. Each entry is a one byte tag, a big-endian `u16` length, and the value.
+
+use crate::cipher::Cipher;
+use crate::compress::Compression;
+use crate::error::{err_value_validation, Result};
+use crate::kdf::Kdf;
+use crate::keyslot::{KeySlot, MAX_KEY_SLOTS};
+use crate::recipient::MAX_RECIPIENTS;
+use crate::{QRSTREAM_MAGIC, QRSTREAM_VERSION};
+use sha2::{Digest, Sha256};
+
+const TAG_CIPHER: u8 = 1;
+const TAG_KDF: u8 = 2;
+const TAG_SALT: u8 = 3;
+const TAG_LENGTH: u8 = 4;
+const TAG_KEY_CHECK: u8 = 5;
+const TAG_DIGEST: u8 = 6;
+const TAG_COMPRESSION: u8 = 7;
+const TAG_METADATA: u8 = 8;
+const TAG_RECIPIENT: u8 = 9;
+const TAG_SIGNATURE: u8 = 10;
+const TAG_KEY_SLOT: u8 = 11;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct StreamHeader {
+    pub(crate) cipher: Cipher,
+    /// KDF used to derive the key from a password. `None` if a raw key was used.
+    pub(crate) kdf: Option<Kdf>,
+    pub(crate) salt: Vec<u8>,
+    /// Length of the payload following the header
+    pub(crate) length: u32,
+    /// Short digest of the key, to tell a wrong password apart from a tampered stream
+    pub(crate) key_check: Vec<u8>,
+    /// SHA-256 of the payload following the header
+    pub(crate) digest: Vec<u8>,
+    /// Compression applied to the data before encryption
+    pub(crate) compression: Compression,
+    /// Whether the data starts with a [crate::metadata::Metadata] block
+    pub(crate) metadata: bool,
+    /// Key of the payload wrapped for each recipient, if encrypted to recipients
+    pub(crate) recipients: Vec<Vec<u8>>,
+    /// Key of the payload wrapped for each password, if encrypted with several of them
+    pub(crate) key_slots: Vec<KeySlot>,
+    /// Ed25519 signature of the stream, as per [StreamHeader::signed_data]
+    pub(crate) signature: Vec<u8>,
+}
+
+impl StreamHeader {
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut entries = Vec::<u8>::new();
+        push_entry(&mut entries, TAG_CIPHER, &[self.cipher.id()]);
+        if let Some(kdf) = &self.kdf {
+            push_entry(&mut entries, TAG_KDF, &kdf.to_bytes());
+        }
+        if !self.salt.is_empty() {
+            push_entry(&mut entries, TAG_SALT, &self.salt);
+        }
+        push_entry(&mut entries, TAG_LENGTH, &self.length.to_be_bytes());
+        if !self.key_check.is_empty() {
+            push_entry(&mut entries, TAG_KEY_CHECK, &self.key_check);
+        }
+        if !self.digest.is_empty() {
+            push_entry(&mut entries, TAG_DIGEST, &self.digest);
+        }
+        if self.compression != Compression::None {
+            push_entry(&mut entries, TAG_COMPRESSION, &[self.compression.id()]);
+        }
+        if self.metadata {
+            push_entry(&mut entries, TAG_METADATA, &[]);
+        }
+        for wrapped_key in &self.recipients {
+            push_entry(&mut entries, TAG_RECIPIENT, wrapped_key);
+        }
+        for key_slot in &self.key_slots {
+            push_entry(&mut entries, TAG_KEY_SLOT, &key_slot.to_bytes());
+        }
+        if !self.signature.is_empty() {
+            push_entry(&mut entries, TAG_SIGNATURE, &self.signature);
+        }
+
+        let mut bytes = Vec::<u8>::with_capacity(entries.len() + 2);
+        bytes.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        bytes.extend(entries);
+        bytes
+    }
+
+    /// Parses the header at the start of `data`, returning it along with the rest of the data.
+    pub(crate) fn parse(data: &[u8]) -> Result<(Self, &[u8])> {
+        let (mut entries, rest) = split_len_prefixed(data)?;
+        let mut header = Self::default();
+        let mut has_cipher = false;
+        while !entries.is_empty() {
+            let (tag, remaining) = entries.split_first().ok_or_else(invalid_header)?;
+            let (value, remaining) = split_len_prefixed(remaining)?;
+            match *tag {
+                TAG_CIPHER => {
+                    let id = value.first().ok_or_else(invalid_header)?;
+                    header.cipher = Cipher::from_id(*id)?;
+                    has_cipher = true;
+                }
+                TAG_KDF => header.kdf = Some(Kdf::from_bytes(value)?),
+                TAG_SALT => header.salt = value.to_vec(),
+                TAG_LENGTH => {
+                    header.length =
+                        u32::from_be_bytes(value.try_into().map_err(|_| invalid_header())?)
+                }
+                TAG_KEY_CHECK => header.key_check = value.to_vec(),
+                TAG_DIGEST => header.digest = value.to_vec(),
+                TAG_COMPRESSION => {
+                    let id = value.first().ok_or_else(invalid_header)?;
+                    header.compression = Compression::from_id(*id)?;
+                }
+                TAG_METADATA => header.metadata = true,
+                TAG_RECIPIENT => header.recipients.push(value.to_vec()),
+                TAG_SIGNATURE => header.signature = value.to_vec(),
+                TAG_KEY_SLOT => header.key_slots.push(KeySlot::from_bytes(value)?),
+                _ => Err(err_value_validation("unsupported stream header"))?,
+            }
+            entries = remaining;
+        }
+        // the cipher entry is mandatory, as it tells whether the stream is encrypted
+        if !has_cipher {
+            Err(invalid_header())?;
+        }
+        if header.key_slots.len() > MAX_KEY_SLOTS || header.recipients.len() > MAX_RECIPIENTS {
+            Err(err_value_validation(format!(
+                "too many key slots or recipients, at most {MAX_KEY_SLOTS} and {MAX_RECIPIENTS} are allowed"
+            )))?;
+        }
+        // anything beyond the length is padding added when splitting the payload into parts
+        if rest.len() < header.length as usize {
+            Err(err_value_validation("stream length mismatch"))?;
+        }
+        let rest = &rest[..header.length as usize];
+        if !header.digest.is_empty() && Sha256::digest(rest).as_slice() != header.digest {
+            Err(err_value_validation("payload checksum mismatch"))?;
+        }
+        Ok((header, rest))
+    }
+
+    /// Canonical serialization of the header, bound to the payload as associated data of the
+    /// AEAD cipher, so that changes to any of its fields are detected on decryption. The digest
+    /// and the signature are left out, as they are computed over the ciphertext.
+    pub(crate) fn associated_data(&self) -> Vec<u8> {
+        let header = Self {
+            digest: Vec::new(),
+            signature: Vec::new(),
+            ..self.clone()
+        };
+        let mut aad = format!("{QRSTREAM_MAGIC}/{QRSTREAM_VERSION};").into_bytes();
+        aad.extend(header.to_bytes());
+        aad
+    }
+
+    /// Data the signature is computed over, i.e. the header without the signature, followed by
+    /// `body`, the (possibly encrypted) payload.
+    pub(crate) fn signed_data(&self, body: &[u8]) -> Vec<u8> {
+        let header = Self {
+            signature: Vec::new(),
+            ..self.clone()
+        };
+        let mut data = format!("{QRSTREAM_MAGIC}/{QRSTREAM_VERSION};signed;").into_bytes();
+        data.extend(header.to_bytes());
+        data.extend_from_slice(body);
+        data
+    }
+}
+
+pub(crate) fn push_entry(entries: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    entries.push(tag);
+    entries.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    entries.extend_from_slice(value);
+}
+
+pub(crate) fn split_len_prefixed(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    if data.len() < 2 {
+        Err(invalid_header())?;
+    }
+    let len = u16::from_be_bytes([data[0], data[1]]) as usize;
+    if data.len() < 2 + len {
+        Err(invalid_header())?;
+    }
+    Ok((&data[2..2 + len], &data[2 + len..]))
+}
+
+fn invalid_header() -> clap::error::Error {
+    err_value_validation("invalid stream header")
+}
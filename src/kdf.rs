@@ -0,0 +1,235 @@
+//! Key derivation functionality. Passwords are turned into encryption keys here, using the
+//! parameters recorded in the stream header.
+
+use crate::error::{err_value_validation, Result};
+use crate::memory::{self, SecretKey};
+use crate::{ClapResult, QRSTREAM_MAGIC};
+use argon2::{Algorithm, Argon2, Params, Version};
+use pbkdf2::pbkdf2_hmac;
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Length of the random salt generated for each stream
+pub(crate) const SALT_LEN: usize = 16;
+
+/// Returns a short digest of `key`, stored in the stream header to verify the key before
+/// decrypting.
+pub(crate) fn key_check(key: &[u8; 32]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(QRSTREAM_MAGIC.as_bytes());
+    hasher.update(b"/key-check;");
+    hasher.update(key);
+    hasher.finalize()[..8].to_vec()
+}
+
+/// Secret material provided by the user, before any key derivation has happened. It's wiped
+/// from memory when dropped.
+#[derive(Clone, Debug, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub(crate) enum Secret {
+    Password(String),
+    Key([u8; 32]),
+    /// Password along with the SHA-256 of a keyfile, both of which are needed for the key
+    KeyfilePassword([u8; 32], String),
+}
+
+impl Secret {
+    /// Returns `true` if this secret needs to go through a KDF to become a key.
+    pub(crate) fn is_password(&self) -> bool {
+        matches!(self, Self::Password(_) | Self::KeyfilePassword(..))
+    }
+
+    /// Locks the memory holding this secret, so that it isn't swapped out. It's meant for
+    /// secrets that stay where they are until the process ends.
+    pub(crate) fn lock(&self) {
+        match self {
+            Self::Password(password) => memory::lock(password.as_bytes()),
+            Self::Key(key) => memory::lock(key),
+            Self::KeyfilePassword(keyfile, password) => {
+                memory::lock(keyfile);
+                memory::lock(password.as_bytes());
+            }
+        }
+    }
+
+    /// Derives the encryption key for this secret. Raw keys are returned as is, and passwords
+    /// are run through `kdf` with `salt`, preceded by the digest of the keyfile if there's one.
+    pub(crate) fn derive_key(&self, kdf: &Kdf, salt: &[u8]) -> Result<SecretKey> {
+        match self {
+            Self::Key(key) => {
+                let mut secret_key = SecretKey::new();
+                secret_key.copy_from_slice(key);
+                Ok(secret_key)
+            }
+            Self::Password(password) => kdf.derive(password.as_bytes(), salt),
+            Self::KeyfilePassword(keyfile, password) => {
+                let mut input = Zeroizing::new(Vec::with_capacity(32 + password.len()));
+                input.extend_from_slice(keyfile);
+                input.extend_from_slice(password.as_bytes());
+                kdf.derive(&input, salt)
+            }
+        }
+    }
+
+    /// Derives the encryption key the way v1 streams do, i.e. with a fixed salt.
+    pub(crate) fn legacy_key(&self) -> Result<SecretKey> {
+        self.derive_key(&Kdf::LEGACY, QRSTREAM_MAGIC.as_bytes())
+    }
+}
+
+/// Key derivation functions, along with their cost parameters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Kdf {
+    Pbkdf2Sha256 {
+        iterations: u32,
+    },
+    /// Argon2id, with memory cost `m` in KiB, time cost `t` and parallelism `p`
+    Argon2id {
+        m: u32,
+        t: u32,
+        p: u32,
+    },
+}
+
+impl Kdf {
+    /// KDF used by v1 streams
+    pub(crate) const LEGACY: Kdf = Kdf::Pbkdf2Sha256 {
+        iterations: 600_000,
+    };
+
+    /// Upper limit of the PBKDF2 iterations, so that a forged header can't keep decode busy
+    /// for hours
+    const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+    /// Upper limits of the Argon2id memory cost (1 GiB), time cost and parallelism, for the same
+    /// reason, and so that decode doesn't run out of memory
+    const MAX_ARGON2ID_M: u32 = 1024 * 1024;
+    const MAX_ARGON2ID_T: u32 = 64;
+    const MAX_ARGON2ID_P: u32 = 64;
+
+    const ID_PBKDF2_SHA256: u8 = 1;
+    const ID_ARGON2ID: u8 = 2;
+
+    /// Parses a KDF specification of the form `<name>[:<param>=<value>,...]`, e.g.
+    /// `pbkdf2:i=600000` or `argon2id:m=65536,t=3,p=4`. Parameters not specified are
+    /// set to their defaults.
+    pub(crate) fn parse(s: &str) -> ClapResult<Self> {
+        let (name, params) = s.split_once(':').unwrap_or((s, ""));
+        let mut kdf = match name {
+            "pbkdf2" => Self::LEGACY,
+            "argon2id" => Self::Argon2id {
+                m: 64 * 1024,
+                t: 3,
+                p: 4,
+            },
+            _ => Err(err_value_validation(format!("invalid kdf {name}")))?,
+        };
+        for param in params.split(',').filter(|p| !p.is_empty()) {
+            let invalid_param = || err_value_validation(format!("invalid kdf parameter {param}"));
+            let (key, value) = param.split_once('=').ok_or_else(invalid_param)?;
+            let value = value.parse::<u32>().map_err(|_| invalid_param())?;
+            match (&mut kdf, key) {
+                (Self::Pbkdf2Sha256 { iterations }, "i") => *iterations = value,
+                (Self::Argon2id { m, .. }, "m") => *m = value,
+                (Self::Argon2id { t, .. }, "t") => *t = value,
+                (Self::Argon2id { p, .. }, "p") => *p = value,
+                _ => Err(invalid_param())?,
+            }
+        }
+        kdf.check_limits()?;
+        Ok(kdf)
+    }
+
+    pub(crate) fn derive(&self, password: &[u8], salt: &[u8]) -> Result<SecretKey> {
+        let mut key = SecretKey::new();
+        match self {
+            Self::Pbkdf2Sha256 { iterations } => {
+                pbkdf2_hmac::<Sha256>(password, salt, *iterations, key.as_mut())
+            }
+            Self::Argon2id { m, t, p } => {
+                let params = Params::new(*m, *t, *p, Some(key.len()))
+                    .map_err(|e| err_value_validation(format!("invalid argon2id params: {e}")))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(password, salt, key.as_mut())
+                    .map_err(|e| err_value_validation(format!("argon2id error: {e}")))?;
+            }
+        }
+        Ok(key)
+    }
+
+    /// Serializes the KDF id and its parameters, for use in the stream header.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::<u8>::new();
+        match self {
+            Self::Pbkdf2Sha256 { iterations } => {
+                bytes.push(Self::ID_PBKDF2_SHA256);
+                bytes.extend_from_slice(&iterations.to_be_bytes());
+            }
+            Self::Argon2id { m, t, p } => {
+                bytes.push(Self::ID_ARGON2ID);
+                for param in [m, t, p] {
+                    bytes.extend_from_slice(&param.to_be_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Parses the output of [Kdf::to_bytes].
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let invalid_kdf = || err_value_validation("invalid kdf parameters");
+        let (id, params) = bytes.split_first().ok_or_else(invalid_kdf)?;
+        match *id {
+            Self::ID_PBKDF2_SHA256 => {
+                let iterations = u32::from_be_bytes(params.try_into().map_err(|_| invalid_kdf())?);
+                let kdf = Self::Pbkdf2Sha256 { iterations };
+                kdf.check_limits()?;
+                Ok(kdf)
+            }
+            Self::ID_ARGON2ID => {
+                if params.len() != 12 {
+                    Err(invalid_kdf())?;
+                }
+                let param = |i: usize| u32::from_be_bytes(params[i..i + 4].try_into().unwrap());
+                let kdf = Self::Argon2id {
+                    m: param(0),
+                    t: param(4),
+                    p: param(8),
+                };
+                kdf.check_limits()?;
+                Ok(kdf)
+            }
+            _ => Err(err_value_validation("unsupported kdf"))?,
+        }
+    }
+
+    /// Fails if the cost parameters are beyond what decode is willing to spend, as they're
+    /// read from the header of untrusted streams.
+    fn check_limits(&self) -> ClapResult<()> {
+        match self {
+            Self::Pbkdf2Sha256 { iterations } if *iterations > Self::MAX_PBKDF2_ITERATIONS => {
+                Err(err_value_validation(format!(
+                    "pbkdf2 iterations {iterations} exceed the limit of {}",
+                    Self::MAX_PBKDF2_ITERATIONS
+                )))
+            }
+            Self::Argon2id { m, .. } if *m > Self::MAX_ARGON2ID_M => {
+                Err(err_value_validation(format!(
+                    "argon2id memory cost {m} KiB exceeds the limit of {} KiB",
+                    Self::MAX_ARGON2ID_M
+                )))
+            }
+            Self::Argon2id { t, .. } if *t > Self::MAX_ARGON2ID_T => {
+                Err(err_value_validation(format!(
+                    "argon2id time cost {t} exceeds the limit of {}",
+                    Self::MAX_ARGON2ID_T
+                )))
+            }
+            Self::Argon2id { p, .. } if *p > Self::MAX_ARGON2ID_P => {
+                Err(err_value_validation(format!(
+                    "argon2id parallelism {p} exceeds the limit of {}",
+                    Self::MAX_ARGON2ID_P
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Key slots, as in LUKS. The payload is encrypted with a random data key, which is wrapped
+//! separately under the key of each password, so that any one of them decrypts the stream.
+
+use crate::cipher::Cipher;
+use crate::error::{err_value_validation, Result};
+use crate::header::{push_entry, split_len_prefixed};
+use crate::kdf::{Kdf, Secret, SALT_LEN};
+use crate::memory::SecretKey;
+use crate::QRSTREAM_MAGIC;
+use aes_gcm::aead::rand_core::{OsRng, RngCore};
+use zeroize::Zeroizing;
+
+/// Maximum number of key slots of a stream. Each one costs a KDF run per password on decoding,
+/// so a forged header can't be allowed to hold any number of them
+pub(crate) const MAX_KEY_SLOTS: usize = 16;
+
+const TAG_KDF: u8 = 1;
+const TAG_SALT: u8 = 2;
+const TAG_WRAPPED_KEY: u8 = 3;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct KeySlot {
+    /// KDF the wrapping key is derived with. `None` if a raw key was used.
+    kdf: Option<Kdf>,
+    salt: Vec<u8>,
+    wrapped_key: Vec<u8>,
+}
+
+impl KeySlot {
+    /// Wraps `data_key` with `cipher`, under the key derived from `secret`. Passwords go through
+    /// `kdf`, with a salt of their own.
+    pub(crate) fn wrap(
+        secret: &Secret,
+        kdf: &Kdf,
+        cipher: Cipher,
+        data_key: &[u8; 32],
+    ) -> Result<Self> {
+        let mut slot = Self {
+            kdf: None,
+            salt: Vec::new(),
+            wrapped_key: Vec::new(),
+        };
+        if secret.is_password() {
+            slot.kdf = Some(kdf.clone());
+            slot.salt = vec![0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut slot.salt);
+        }
+        let key = slot.derive_key(secret)?;
+        slot.wrapped_key = cipher.encrypt(&key, data_key, &associated_data())?;
+        Ok(slot)
+    }
+
+    /// Reverses [KeySlot::wrap], returning `None` if `secret` isn't the one of this slot.
+    pub(crate) fn unwrap(&self, secret: &Secret, cipher: Cipher) -> Result<Option<SecretKey>> {
+        let key = self.derive_key(secret)?;
+        Ok(cipher
+            .decrypt(&key, &self.wrapped_key, &associated_data())
+            .ok()
+            .map(Zeroizing::new)
+            .and_then(|data_key| SecretKey::from_slice(&data_key)))
+    }
+
+    fn derive_key(&self, secret: &Secret) -> Result<SecretKey> {
+        secret.derive_key(self.kdf.as_ref().unwrap_or(&Kdf::LEGACY), &self.salt)
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut entries = Vec::<u8>::new();
+        if let Some(kdf) = &self.kdf {
+            push_entry(&mut entries, TAG_KDF, &kdf.to_bytes());
+        }
+        if !self.salt.is_empty() {
+            push_entry(&mut entries, TAG_SALT, &self.salt);
+        }
+        push_entry(&mut entries, TAG_WRAPPED_KEY, &self.wrapped_key);
+        entries
+    }
+
+    /// Parses the output of [KeySlot::to_bytes].
+    pub(crate) fn from_bytes(mut entries: &[u8]) -> Result<Self> {
+        let invalid_key_slot = || err_value_validation("invalid key slot");
+        let mut slot = Self {
+            kdf: None,
+            salt: Vec::new(),
+            wrapped_key: Vec::new(),
+        };
+        while !entries.is_empty() {
+            let (tag, remaining) = entries.split_first().ok_or_else(invalid_key_slot)?;
+            let (value, remaining) =
+                split_len_prefixed(remaining).map_err(|_| invalid_key_slot())?;
+            match *tag {
+                TAG_KDF => slot.kdf = Some(Kdf::from_bytes(value)?),
+                TAG_SALT => slot.salt = value.to_vec(),
+                TAG_WRAPPED_KEY => slot.wrapped_key = value.to_vec(),
+                _ => Err(invalid_key_slot())?,
+            }
+            entries = remaining;
+        }
+        if slot.wrapped_key.is_empty() {
+            Err(invalid_key_slot())?;
+        }
+        Ok(slot)
+    }
+}
+
+fn associated_data() -> Vec<u8> {
+    format!("{QRSTREAM_MAGIC}/key-slot;").into_bytes()
+}
@@ -0,0 +1,73 @@
+//! Labels written above the codes of a sheet, e.g. to tell the shares of a stream apart once
+//! printed. PNG output has no means to render text, so labels are drawn with a small bitmap
+//! font, which covers the characters of share labels only.
+
+use image::{imageops, GrayImage, Luma};
+
+/// Height of the band added above the codes for the label, in pixels
+pub(crate) const LABEL_HEIGHT: u32 = 48;
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+const MAX_SCALE: u32 = 4;
+
+/// Returns `img` with a band above it, holding `text` in black on white. The text is shrunk
+/// if need be to fit the width of the image, and characters the font lacks are left blank.
+pub(crate) fn with_label(img: &GrayImage, text: &str, margin: u32) -> GrayImage {
+    let mut labelled = GrayImage::new(img.width(), img.height() + LABEL_HEIGHT);
+    labelled.fill(255);
+    imageops::replace(&mut labelled, img, 0, LABEL_HEIGHT.into());
+
+    // each character takes the width of a glyph, plus a column of spacing
+    let advance = GLYPH_WIDTH + 1;
+    let available = img.width().saturating_sub(2 * margin);
+    let scale = (available / (advance * text.chars().count().max(1) as u32)).clamp(1, MAX_SCALE);
+    let top = (LABEL_HEIGHT - GLYPH_HEIGHT * scale) / 2;
+    for (idx, c) in text.chars().enumerate() {
+        let left = margin + idx as u32 * advance * scale;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = left + col * scale + dx;
+                        let y = top + row as u32 * scale + dy;
+                        if x < labelled.width() {
+                            labelled.put_pixel(x, y, Luma([0]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    labelled
+}
+
+/// Rows of the 5x7 glyph of `c`, top first, with the leftmost pixel in the highest bit.
+fn glyph(c: char) -> [u8; 7] {
+    match c {
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        'a' => [0x00, 0x00, 0x0e, 0x01, 0x0f, 0x11, 0x0f],
+        'd' => [0x01, 0x01, 0x0d, 0x13, 0x11, 0x11, 0x0f],
+        'e' => [0x00, 0x00, 0x0e, 0x11, 0x1f, 0x10, 0x0e],
+        'f' => [0x06, 0x09, 0x08, 0x1c, 0x08, 0x08, 0x08],
+        'h' => [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x11],
+        'n' => [0x00, 0x00, 0x16, 0x19, 0x11, 0x11, 0x11],
+        'o' => [0x00, 0x00, 0x0e, 0x11, 0x11, 0x11, 0x0e],
+        'r' => [0x00, 0x00, 0x16, 0x19, 0x10, 0x10, 0x10],
+        's' => [0x00, 0x00, 0x0e, 0x10, 0x0e, 0x01, 0x1e],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08],
+        _ => [0; 7],
+    }
+}
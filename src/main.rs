@@ -1,41 +1,70 @@
+mod animation;
+mod base45;
 mod camera;
+mod cipher;
+mod compress;
 mod console;
 mod decode;
 mod encode;
 mod error;
+mod fountain;
+mod header;
+mod kdf;
+mod keyslot;
+mod label;
+mod memory;
+mod metadata;
+mod pdf;
+mod pinentry;
+mod recipient;
+mod shamir;
+mod signature;
+mod structured_append;
+mod terminal;
 
 use camera::get_content_from_camera;
 use clap::{Parser, Subcommand};
 use error::{Result, UnwrapOrExit};
-use pbkdf2::pbkdf2_hmac;
-use sha2::Sha256;
+use kdf::Secret;
+use memory::SecretVec;
+use sha2::{Digest, Sha256};
 use std::io::Read;
+use std::mem::ManuallyDrop;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
+    memory::disable_core_dumps();
     let mut options = QRStreamOptions::parse();
-    options.key = options
+    let description = options.password_description();
+    options.secrets = options
         .password
-        .as_ref()
-        .map(|x| x.get_key().unwrap_or_exit());
+        .iter()
+        .map(|x| x.get_secret(&description).unwrap_or_exit())
+        .collect();
+    options.secrets.iter().for_each(Secret::lock);
     let result = match &options.command {
         QRStreamCommand::Encode(_) => encode::encode(&options).await,
-        QRStreamCommand::Decode => decode::decode(&options).await,
-        QRStreamCommand::ShowKey => {
-            show_key(options.key.as_ref().map(|k| k.as_ref()));
-            Ok(())
-        }
+        QRStreamCommand::Decode(_) => decode::decode(&options).await,
+        QRStreamCommand::ShowKey => show_key(options.secrets.first()),
     };
     result.unwrap_or_exit();
 }
 
-fn show_key(key: Option<&[u8]>) {
-    if let Some(key) = key {
+/// Prints the key derived from `secret` the v1 way, i.e. without a per-stream salt.
+fn show_key(secret: Option<&Secret>) -> Result<()> {
+    if let Some(secret) = secret {
+        let key = secret.legacy_key()?;
         let hexkey = key.iter().map(|b| format!("{:02x}", b)).collect::<String>();
         println!("{hexkey}");
     } else {
         println!("No key set");
     }
+    Ok(())
 }
 
 type ClapResult<T> = std::result::Result<T, clap::Error>;
@@ -43,14 +72,14 @@ type ClapResult<T> = std::result::Result<T, clap::Error>;
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 struct QRStreamOptions {
-    #[arg(short, long, help = "Input source (stdin | camera | env:<varname> | <file>)", default_value = "stdin", value_parser = InputSource::parse)]
-    input: InputSource,
+    #[arg(short, long, help = "Input source (stdin | camera | env:<varname> | <file>); decode accepts several, e.g. one per share", default_value = "stdin", value_parser = InputSource::parse)]
+    input: Vec<InputSource>,
 
-    #[arg(short, long, help = "Encryption password (prompt | env:<varname> | key:<hex> | <value>)", value_parser = PasswordSource::parse)]
-    password: Option<PasswordSource>,
+    #[arg(short, long, help = "Encryption password (prompt | env:<varname> | key:<hex> | file:<keyfile>[+<source>] | fd:<n> | cmd:<command> | pinentry[:<program>] | <value>); encode accepts several, any one of which decrypts", value_parser = PasswordSource::parse)]
+    password: Vec<PasswordSource>,
 
     #[clap(skip)]
-    key: Option<[u8; 32]>,
+    secrets: Vec<Secret>,
 
     #[command(subcommand)]
     command: QRStreamCommand,
@@ -64,6 +93,32 @@ impl QRStreamOptions {
             panic!("encode options requested for non-encode command")
         }
     }
+
+    fn decode_options(&self) -> &DecodeOptions {
+        if let QRStreamCommand::Decode(options) = &self.command {
+            options
+        } else {
+            panic!("decode options requested for non-decode command")
+        }
+    }
+
+    /// Tells what a password is asked for, naming the stream, for prompts that aren't in a
+    /// terminal the command was run from.
+    fn password_description(&self) -> String {
+        let inputs = self
+            .input
+            .iter()
+            .map(InputSource::describe)
+            .collect::<Vec<_>>()
+            .join(", ");
+        match self.command {
+            QRStreamCommand::Encode(_) => format!("Password to encrypt {inputs} into a QR stream"),
+            QRStreamCommand::Decode(_) => {
+                format!("Password to decrypt the QR stream from {inputs}")
+            }
+            QRStreamCommand::ShowKey => "Password to show the key of".to_string(),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -89,33 +144,58 @@ impl InputSource {
         }
     }
 
-    async fn get_content(&self) -> Result<Vec<u8>> {
+    fn describe(&self) -> String {
+        match self {
+            Self::Stdin => "stdin".to_string(),
+            Self::Camera => "the camera".to_string(),
+            Self::Env(varname) => format!("env var {varname}"),
+            Self::File(path) => path.to_string(),
+        }
+    }
+
+    /// Reads the content of this source, which may be the plaintext, into locked memory. Files
+    /// are read into a buffer sized from their metadata.
+    async fn get_content(&self) -> Result<SecretVec> {
         match self {
             Self::Stdin => {
-                let mut data = Vec::<u8>::new();
-                std::io::stdin().read_to_end(&mut data)?;
-                Ok(data)
+                // read from the descriptor directly, as the buffer of io::stdin() would keep a
+                // copy of the data, and without closing it once done
+                let mut stdin =
+                    ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(libc::STDIN_FILENO) });
+                let size = stdin.metadata().map(|m| m.len() as usize).unwrap_or(0);
+                Ok(SecretVec::read_from(&mut *stdin, size)?)
             }
-            Self::Camera => get_content_from_camera().await,
-            Self::Env(varname) => Ok(std::env::var(varname)
-                .map_err(|_| error::err_value_validation("invalid env var"))?
-                .as_bytes()
-                .to_vec()),
+            Self::Camera => Ok(SecretVec::new(get_content_from_camera().await?)),
+            Self::Env(varname) => Ok(SecretVec::new(
+                std::env::var(varname)
+                    .map_err(|_| error::err_value_validation("invalid env var"))?
+                    .into_bytes(),
+            )),
             Self::File(path) => {
                 let mut file = std::fs::File::open(path)?;
-                let mut data = Vec::<u8>::new();
-                file.read_to_end(&mut data)?;
-                Ok(data)
+                let size = file.metadata().map(|m| m.len() as usize).unwrap_or(0);
+                Ok(SecretVec::read_from(&mut file, size)?)
             }
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Where a password comes from. Passwords given as is are wiped along with it.
+#[derive(Clone, Debug, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
 enum PasswordSource {
     Prompt,
     Env(String),
     Key([u8; 32]),
+    /// Keyfile, whose SHA-256 is used as the key
+    File(String),
+    /// Keyfile combined with a password from another source, so that both are needed
+    FileWith(String, Box<PasswordSource>),
+    /// Inherited file descriptor, to read the password from
+    Fd(RawFd),
+    /// Shell command, whose output is the password
+    Command(String),
+    /// Pinentry program, to ask for the password over the Assuan protocol
+    Pinentry(String),
     Value(String),
 }
 
@@ -123,13 +203,17 @@ impl PasswordSource {
     fn parse(s: &str) -> ClapResult<Self> {
         Ok(if s == "prompt" {
             Self::Prompt
+        } else if s == "pinentry" {
+            Self::Pinentry(pinentry::DEFAULT_PROGRAM.to_string())
+        } else if let Some(program) = s.strip_prefix("pinentry:") {
+            Self::Pinentry(program.to_string())
         } else if let Some(envkey) = s.strip_prefix("env:") {
             Self::Env(envkey.to_string())
         } else if let Some(hexkey) = s.strip_prefix("key:") {
             let hkl = hexkey.len();
             let mut key = Vec::<u8>::new();
             let x = hkl % 2;
-            for idx in 0..(hkl + 1) / 2 {
+            for idx in 0..hkl.div_ceil(2) {
                 if idx == 0 && hkl % 2 != 0 {
                     key.push(
                         u8::from_str_radix(&hexkey[0..1], 16)
@@ -145,37 +229,131 @@ impl PasswordSource {
                 }
             }
             Self::Key(key.try_into().map_err(|_| error::err_invalid_input())?)
+        } else if let Some(rest) = s.strip_prefix("file:") {
+            // the keyfile is combined with a password if followed by `+<source>`, with a
+            // source that asks for one, so that other paths with a `+` are taken as is
+            rest.match_indices('+')
+                .find_map(|(idx, _)| match Self::parse(&rest[idx + 1..]) {
+                    Ok(
+                        source @ (Self::Prompt
+                        | Self::Pinentry(_)
+                        | Self::Env(_)
+                        | Self::Fd(_)
+                        | Self::Command(_)),
+                    ) => Some(Self::FileWith(rest[..idx].to_string(), Box::new(source))),
+                    _ => None,
+                })
+                .unwrap_or_else(|| Self::File(rest.to_string()))
+        } else if let Some(fd) = s.strip_prefix("fd:") {
+            Self::Fd(
+                fd.parse::<RawFd>()
+                    .ok()
+                    .filter(|fd| *fd >= 0)
+                    .ok_or_else(error::err_invalid_input)?,
+            )
+        } else if let Some(command) = s.strip_prefix("cmd:") {
+            Self::Command(command.to_string())
         } else {
             Self::Value(s.to_string())
         })
     }
 
-    fn get_key(&self) -> Result<[u8; 32]> {
+    /// Gets the secret from this source. `description` tells what it's for, when it's asked
+    /// for outside of the terminal.
+    fn get_secret(&self, description: &str) -> Result<Secret> {
         let password = match self {
+            // without a terminal, e.g. when run from a GUI launcher, pinentry is asked instead
+            Self::Prompt if !console::has_tty() => {
+                pinentry::get_pin(pinentry::DEFAULT_PROGRAM, description)?
+            }
             Self::Prompt => console::prompt("Enter password: ", true)?,
             Self::Env(varname) => std::env::var(varname)
                 .map_err(|_| error::err_value_validation("invalid env var"))?,
-            Self::Key(key) => return Ok(key.to_owned()),
+            Self::Key(key) => return Ok(Secret::Key(key.to_owned())),
+            Self::File(path) => return Ok(Secret::Key(read_keyfile(path)?)),
+            Self::FileWith(path, source) => {
+                let keyfile = read_keyfile(path)?;
+                let Secret::Password(password) = &mut source.get_secret(description)? else {
+                    unreachable!("keyfiles are only combined with sources of passwords")
+                };
+                return Ok(Secret::KeyfilePassword(keyfile, std::mem::take(password)));
+            }
+            Self::Fd(fd) => {
+                // the password is read from a duplicate, which is closed once read, so that the
+                // descriptor itself stays open, e.g. stdin with fd:0, or stdout with fd:1
+                let copy = unsafe { libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, 0) };
+                if copy == -1 {
+                    Err(error::err_value_validation(format!(
+                        "invalid file descriptor {fd}"
+                    )))?;
+                }
+                let mut password = Zeroizing::new(String::new());
+                unsafe { std::fs::File::from_raw_fd(copy) }.read_to_string(&mut password)?;
+                password.trim_end_matches(['\r', '\n']).to_string()
+            }
+            Self::Command(command) => run_password_command(command)?,
+            Self::Pinentry(program) => pinentry::get_pin(program, description)?,
             Self::Value(value) => value.to_owned(),
         };
-        let salt = QRSTREAM_MAGIC.as_bytes();
-        let num_iterations = 600_000;
-        let mut key = [0u8; 32];
-        pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, num_iterations, &mut key);
-        Ok(key)
+        Ok(Secret::Password(password))
     }
 }
 
+/// Returns the SHA-256 of the keyfile at `path`.
+fn read_keyfile(path: &str) -> Result<[u8; 32]> {
+    let content =
+        Zeroizing::new(std::fs::read(path).map_err(|e| {
+            error::err_value_validation(format!("cannot read keyfile {path}: {e}"))
+        })?);
+    Ok(Sha256::digest(&content).into())
+}
+
+/// Time a password command is given to run, which includes any prompting it does of its own
+const PASSWORD_COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Runs `command` with the shell, returning its trimmed output. Its stdin and stderr are left
+/// to the terminal, for helpers that prompt.
+fn run_password_command(command: &str) -> Result<String> {
+    let mut child = std::process::Command::new("sh")
+        .args(["-c", command])
+        .stdout(Stdio::piped())
+        .spawn()?;
+    // the output is read separately, so that a command filling the pipe can still finish
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = std::thread::spawn(move || {
+        let mut output = Zeroizing::new(String::new());
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+    let deadline = Instant::now() + PASSWORD_COMMAND_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        } else if Instant::now() >= deadline {
+            _ = child.kill();
+            _ = child.wait();
+            Err(error::err_value_validation("password command timed out"))?;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    if !status.success() {
+        Err(error::err_value_validation(format!(
+            "password command failed ({status})"
+        )))?;
+    }
+    let output = reader.join().expect("password command reader panicked")?;
+    Ok(output.trim().to_string())
+}
+
 #[derive(Debug, Subcommand)]
 enum QRStreamCommand {
     Encode(EncodeOptions),
-    Decode,
+    Decode(DecodeOptions),
     ShowKey,
 }
 
 #[derive(Debug, Parser)]
 struct EncodeOptions {
-    #[arg(short, long, help = "Output format (png | txt)", default_value = "png", value_parser = OutputFormat::parse)]
+    #[arg(short, long, help = "Output format (png | svg | pdf | term | gif | apng | txt)", default_value = "png", value_parser = OutputFormat::parse)]
     out_format: OutputFormat,
 
     #[arg(long, help = "Error correction level (L|M|Q|H)", default_value = "Q", value_parser = parse_ec_level)]
@@ -187,18 +365,150 @@ struct EncodeOptions {
         default_value = "1"
     )]
     qr_per_row: u32,
+
+    #[arg(long, help = "Caption each QR code with its part number (svg)")]
+    captions: bool,
+
+    #[arg(long, help = "Paper size (a4 | letter) (pdf)", default_value = "a4", value_parser = pdf::PaperSize::parse)]
+    paper: pdf::PaperSize,
+
+    #[arg(long, help = "Size of a QR code module, in mm (pdf)", default_value = "0.5", value_parser = pdf::parse_module_size)]
+    module_size: f32,
+
+    #[arg(long, help = "Label to caption the QR codes with (pdf)")]
+    label: Option<String>,
+
+    #[arg(long, help = "How to draw QR codes in the terminal (blocks | kitty | sixel) (term)", default_value = "blocks", value_parser = terminal::TermGraphics::parse)]
+    term_graphics: terminal::TermGraphics,
+
+    #[arg(
+        long,
+        help = "Show the QR codes one at a time, waiting for a key, and clear the screen and scrollback after (term)"
+    )]
+    one_at_a_time: bool,
+
+    #[arg(long, help = "Frames per second, each frame showing one QR code (gif, apng)", default_value = "2", value_parser = animation::parse_frame_rate)]
+    frame_rate: f32,
+
+    #[arg(
+        long,
+        help = "Key derivation function for passwords (pbkdf2[:i=<iterations>] | argon2id[:m=<KiB>,t=<passes>,p=<lanes>])",
+        default_value = "pbkdf2",
+        value_parser = kdf::Kdf::parse
+    )]
+    kdf: kdf::Kdf,
+
+    #[arg(long, help = "Cipher to encrypt with (aes256gcm | chacha20poly1305 | xchacha20poly1305)", default_value = "aes256gcm", value_parser = cipher::Cipher::parse)]
+    cipher: cipher::Cipher,
+
+    #[arg(
+        long = "recipient",
+        help = "Encrypt to this age X25519 public key (age1...), in addition to any passwords; can be repeated",
+        value_parser = recipient::Recipient::parse
+    )]
+    recipients: Vec<recipient::Recipient>,
+
+    #[arg(
+        long,
+        help = "File with the Ed25519 private key (PKCS#8 PEM) to sign the stream with"
+    )]
+    sign_key: Option<String>,
+
+    #[arg(long, help = "Compress the data before encrypting it (none | deflate | zstd | auto)", default_value = "none", value_parser = compress::CompressionMode::parse)]
+    compress: compress::CompressionMode,
+
+    #[arg(
+        long,
+        help = "Extra parity QR codes to add, so that as many can be lost and still decoded",
+        default_value = "0"
+    )]
+    parity: usize,
+
+    #[arg(
+        long,
+        help = "Emit this many fountain coded QR codes instead, any large enough subset of which can be decoded",
+        conflicts_with = "parity"
+    )]
+    fountain: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Mark multiple QR codes as a Structured Append set, for standard scanners to join",
+        conflicts_with = "fountain"
+    )]
+    structured_append: bool,
+
+    #[arg(
+        long,
+        help = "Split the data into this many Shamir shares, each on its own sheet for a different custodian",
+        requires = "threshold"
+    )]
+    shares: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Number of shares needed to decode, when splitting into shares",
+        requires = "shares"
+    )]
+    threshold: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Store the filename, content type, size and creation time of the input, encrypted along with it"
+    )]
+    metadata: bool,
+}
+
+#[derive(Debug, Parser)]
+struct DecodeOptions {
+    #[arg(
+        long,
+        help = "Write to the original filename stored in the stream, instead of stdout"
+    )]
+    restore_name: bool,
+
+    #[arg(
+        long,
+        help = "File with the age identity (AGE-SECRET-KEY-1...) to decrypt a stream encrypted to recipients"
+    )]
+    identity: Option<String>,
+
+    #[arg(
+        long,
+        help = "File with the Ed25519 public key (PEM) the stream must be signed with"
+    )]
+    verify_key: Option<String>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 enum OutputFormat {
+    Apng,
+    Gif,
+    Pdf,
     Png,
+    Svg,
+    Term,
     Txt,
 }
 
 impl OutputFormat {
+    /// Whether the format can hold several sets of codes in one document, e.g. one for each
+    /// share, as a labelled sheet each, or as lines tagged with the share for txt.
+    fn has_sheets(&self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Pdf | OutputFormat::Png | OutputFormat::Svg | OutputFormat::Txt
+        )
+    }
+
     fn parse(s: &str) -> ClapResult<Self> {
         match s {
+            "apng" => Ok(OutputFormat::Apng),
+            "gif" => Ok(OutputFormat::Gif),
+            "pdf" => Ok(OutputFormat::Pdf),
             "png" => Ok(OutputFormat::Png),
+            "svg" => Ok(OutputFormat::Svg),
+            "term" => Ok(OutputFormat::Term),
             "txt" => Ok(OutputFormat::Txt),
             _ => Err(error::err_value_validation(format!(
                 "invalid output format {s}"
@@ -219,8 +529,36 @@ fn parse_ec_level(s: &str) -> ClapResult<qr_code::EcLevel> {
     }
 }
 
+/// Creates a new file at `path` that only its owner can access, refusing to overwrite an
+/// existing one.
+pub(crate) fn create_private_file(path: &std::path::Path) -> Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => {
+                error::Error::from(error::err_value_validation(format!(
+                    "{} already exists, refusing to overwrite it",
+                    path.display()
+                )))
+            }
+            _ => error::Error::from(e),
+        })
+}
+
+/// Computes the checksum written with each part of a v2 stream, over the text of its data.
+pub(crate) fn part_checksum(data: &str) -> u16 {
+    crc::Crc::<u16>::new(&crc::CRC_16_IBM_3740).checksum(data.as_bytes())
+}
+
 /// Magic string to identify a QR stream
 pub(crate) const QRSTREAM_MAGIC: &str = "QRST";
 
 /// Current version of QR stream serialized format
-pub(crate) const QRSTREAM_VERSION: u8 = 1;
+pub(crate) const QRSTREAM_VERSION: u8 = 2;
+
+/// Original version of QR stream serialized format, which is only decoded now. It is limited to
+/// 15 parts, and carries no header.
+pub(crate) const QRSTREAM_VERSION_V1: u8 = 1;
@@ -0,0 +1,169 @@
+//! Memory hygiene for secret material. Buffers holding passwords, keys or plaintext are locked
+//! into memory so that they aren't swapped out, and wiped once done with, while core dumps are
+//! disabled for the whole process.
+//!
+//! Locked pages are never unlocked, as `munlock` isn't counted, and would unlock any other
+//! secret sharing a page with the one being dropped. The process is short lived anyway.
+
+use std::io::{self, Read, Write};
+use std::ops::{Deref, DerefMut};
+use zeroize::Zeroize;
+
+/// Keeps the memory of this process out of core dumps, and out of reach of debuggers run by
+/// other processes of the same user. This is best effort, and failures are ignored.
+pub(crate) fn disable_core_dumps() {
+    let no_core = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    unsafe {
+        libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+        #[cfg(target_os = "linux")]
+        libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+    }
+}
+
+/// Locks the pages holding `data` into memory. This is best effort, as it's subject to
+/// `RLIMIT_MEMLOCK`.
+pub(crate) fn lock(data: &[u8]) {
+    if !data.is_empty() {
+        unsafe { libc::mlock(data.as_ptr().cast(), data.len()) };
+    }
+}
+
+/// Buffer of secret data, locked into memory while it's alive, and wiped when dropped. It only
+/// ever grows into a new buffer, with the old one wiped, so that no copy of the data is left
+/// behind in freed memory, as a reallocation of a plain `Vec` would.
+pub(crate) struct SecretVec(Vec<u8>);
+
+impl SecretVec {
+    pub(crate) fn new(data: Vec<u8>) -> Self {
+        if data.capacity() > 0 {
+            unsafe { libc::mlock(data.as_ptr().cast(), data.capacity()) };
+        }
+        Self(data)
+    }
+
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self::new(Vec::with_capacity(capacity))
+    }
+
+    /// Reads `reader` to its end, into a buffer of `size_hint` bytes to start with, e.g. the
+    /// size of the file being read.
+    pub(crate) fn read_from(reader: &mut impl Read, size_hint: usize) -> io::Result<Self> {
+        // one more byte, so that the read telling the end has been reached needs no growth
+        let mut secret = Self::with_capacity(size_hint.saturating_add(1).max(8192));
+        // the spare capacity is zeroed to be read into, only once per buffer, as it doesn't
+        // reallocate
+        let mut len = 0;
+        secret.0.resize(secret.0.capacity(), 0);
+        loop {
+            if len == secret.0.len() {
+                secret.0.truncate(len);
+                secret.reserve(1);
+                secret.0.resize(secret.0.capacity(), 0);
+            }
+            match reader.read(&mut secret.0[len..]) {
+                Ok(0) => {
+                    secret.0.truncate(len);
+                    return Ok(secret);
+                }
+                Ok(read) => len += read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub(crate) fn extend_from_slice(&mut self, data: &[u8]) {
+        self.reserve(data.len());
+        self.0.extend_from_slice(data);
+    }
+
+    /// Makes room for `additional` more bytes, moving the data to a larger buffer if need be.
+    fn reserve(&mut self, additional: usize) {
+        let needed = self.0.len() + additional;
+        if needed <= self.0.capacity() {
+            return;
+        }
+        let mut grown = Self::with_capacity(needed.max(self.0.capacity() * 2));
+        grown.0.extend_from_slice(&self.0);
+        // the old buffer is wiped as it's dropped
+        *self = grown;
+    }
+}
+
+impl From<Vec<u8>> for SecretVec {
+    fn from(data: Vec<u8>) -> Self {
+        Self::new(data)
+    }
+}
+
+impl Deref for SecretVec {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Write for SecretVec {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SecretVec {
+    fn drop(&mut self) {
+        // the spare capacity is wiped too, as it may hold data from before a truncation
+        self.0.zeroize();
+    }
+}
+
+/// Encryption key, kept on the heap so that it stays at the one address that's locked, rather
+/// than being copied around the stack as it's moved. It's wiped when dropped.
+pub(crate) struct SecretKey(Box<[u8; 32]>);
+
+impl SecretKey {
+    /// Returns an all zero key, to be filled in.
+    pub(crate) fn new() -> Self {
+        let key = Box::new([0u8; 32]);
+        lock(key.as_slice());
+        Self(key)
+    }
+
+    /// Returns a copy of `key`, or `None` if it isn't 32 bytes long.
+    pub(crate) fn from_slice(key: &[u8]) -> Option<Self> {
+        if key.len() != 32 {
+            return None;
+        }
+        let mut secret_key = Self::new();
+        secret_key.copy_from_slice(key);
+        Some(secret_key)
+    }
+}
+
+impl Deref for SecretKey {
+    type Target = [u8; 32];
+
+    fn deref(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl DerefMut for SecretKey {
+    fn deref_mut(&mut self) -> &mut [u8; 32] {
+        &mut self.0
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
@@ -0,0 +1,126 @@
+//! Metadata of the encoded file. It's stored at the start of the data, so it gets compressed and
+//! encrypted along with it, and uses the same entry format as the stream header.
+
+use crate::error::{err_value_validation, Result};
+use crate::header::{push_entry, split_len_prefixed};
+use crate::InputSource;
+use std::ffi::OsStr;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const TAG_FILENAME: u8 = 1;
+const TAG_CONTENT_TYPE: u8 = 2;
+const TAG_LENGTH: u8 = 3;
+const TAG_CREATED: u8 = 4;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Metadata {
+    /// Name of the file, without any directories
+    pub(crate) filename: Option<String>,
+    pub(crate) content_type: Option<String>,
+    /// Length of the data, in bytes
+    pub(crate) length: u64,
+    /// Creation time of the file, in seconds since the unix epoch
+    pub(crate) created: Option<u64>,
+}
+
+impl Metadata {
+    /// Collects the metadata of `data`, read from `input`. Only the length is known for inputs
+    /// other than files.
+    pub(crate) fn collect(input: &InputSource, data: &[u8]) -> Result<Self> {
+        let mut metadata = Self {
+            length: data.len() as u64,
+            ..Self::default()
+        };
+        if let InputSource::File(path) = input {
+            let path = Path::new(path);
+            metadata.filename = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned());
+            metadata.content_type = mime_guess::from_path(path)
+                .first()
+                .map(|mime| mime.to_string());
+            let fs_metadata = std::fs::metadata(path)?;
+            metadata.created = fs_metadata
+                .created()
+                .or_else(|_| fs_metadata.modified())
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs());
+        }
+        Ok(metadata)
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut entries = Vec::<u8>::new();
+        if let Some(filename) = &self.filename {
+            push_entry(&mut entries, TAG_FILENAME, filename.as_bytes());
+        }
+        if let Some(content_type) = &self.content_type {
+            push_entry(&mut entries, TAG_CONTENT_TYPE, content_type.as_bytes());
+        }
+        push_entry(&mut entries, TAG_LENGTH, &self.length.to_be_bytes());
+        if let Some(created) = self.created {
+            push_entry(&mut entries, TAG_CREATED, &created.to_be_bytes());
+        }
+
+        let mut bytes = Vec::<u8>::with_capacity(entries.len() + 2);
+        bytes.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        bytes.extend(entries);
+        bytes
+    }
+
+    /// Reads the metadata at the start of the decompressed data, leaving `reader` at the data
+    /// itself.
+    pub(crate) fn read(reader: &mut impl Read) -> Result<Self> {
+        let mut len = [0u8; 2];
+        reader
+            .read_exact(&mut len)
+            .map_err(|_| invalid_metadata())?;
+        let mut entries = vec![0u8; u16::from_be_bytes(len) as usize];
+        reader
+            .read_exact(&mut entries)
+            .map_err(|_| invalid_metadata())?;
+        let mut entries = entries.as_slice();
+        let mut metadata = Self::default();
+        while !entries.is_empty() {
+            let (tag, remaining) = entries.split_first().ok_or_else(invalid_metadata)?;
+            let (value, remaining) =
+                split_len_prefixed(remaining).map_err(|_| invalid_metadata())?;
+            let string = || String::from_utf8(value.to_vec()).map_err(|_| invalid_metadata());
+            let number = || -> Result<u64> {
+                Ok(u64::from_be_bytes(
+                    value.try_into().map_err(|_| invalid_metadata())?,
+                ))
+            };
+            match *tag {
+                TAG_FILENAME => metadata.filename = Some(string()?),
+                TAG_CONTENT_TYPE => metadata.content_type = Some(string()?),
+                TAG_LENGTH => metadata.length = number()?,
+                TAG_CREATED => metadata.created = Some(number()?),
+                // unknown entries are skipped, as metadata is only informational
+                _ => {}
+            }
+            entries = remaining;
+        }
+        Ok(metadata)
+    }
+
+    /// Path to restore the file to, i.e. its name in the current directory.
+    pub(crate) fn restore_path(&self) -> Result<PathBuf> {
+        let filename = self
+            .filename
+            .as_deref()
+            .ok_or_else(|| err_value_validation("stream has no filename to restore"))?;
+        // the name comes from the stream, so it mustn't be able to point anywhere else
+        match Path::new(filename).file_name() {
+            Some(name) if name == OsStr::new(filename) => Ok(PathBuf::from(name)),
+            _ => Err(err_value_validation(format!("invalid filename {filename}")))?,
+        }
+    }
+}
+
+fn invalid_metadata() -> clap::error::Error {
+    err_value_validation("invalid file metadata")
+}
@@ -0,0 +1,241 @@
+//! Print-ready PDF output. The codes are laid out on A4 or Letter pages at a physical module
+//! size, each with a caption, and every page has a footer telling how to restore the data.
+
+use crate::encode::{Sheet, QUIET_ZONE};
+use crate::error::{err_value_validation, Result};
+use crate::{ClapResult, EncodeOptions, QRSTREAM_MAGIC, QRSTREAM_VERSION};
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str, TextStr};
+use qr_code::QrCode;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Points per millimetre, as PDF units are 1/72 of an inch
+const PT_PER_MM: f32 = 72.0 / 25.4;
+const MARGIN: f32 = 15.0 * PT_PER_MM;
+const FONT: Name = Name(b"F1");
+const FONT_SIZE: f32 = 8.0;
+const LINE_HEIGHT: f32 = 10.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PaperSize {
+    A4,
+    Letter,
+}
+
+impl PaperSize {
+    pub(crate) fn parse(s: &str) -> ClapResult<Self> {
+        match s {
+            "a4" => Ok(Self::A4),
+            "letter" => Ok(Self::Letter),
+            _ => Err(err_value_validation(format!("invalid paper size {s}"))),
+        }
+    }
+
+    /// Width and height, in points
+    fn dimensions(&self) -> (f32, f32) {
+        match self {
+            Self::A4 => (595.0, 842.0),
+            Self::Letter => (612.0, 792.0),
+        }
+    }
+}
+
+/// Parses a module size in millimetres.
+pub(crate) fn parse_module_size(s: &str) -> ClapResult<f32> {
+    match s.parse::<f32>() {
+        Ok(size) if size.is_finite() && size > 0.0 => Ok(size),
+        _ => Err(err_value_validation(format!("invalid module size {s}"))),
+    }
+}
+
+/// Writes the sheets as a PDF, the codes of each on pages of their own, as many to a page as fit
+/// at the module size asked for. The label of a sheet, e.g. the share it is, goes in the caption
+/// of each of its codes and in the footer of each of its pages.
+pub(crate) fn write_as_pdf(
+    sheets: &[Sheet],
+    options: &EncodeOptions,
+    out: &mut impl Write,
+) -> Result<()> {
+    let (page_width, page_height) = options.paper.dimensions();
+    let module = options.module_size * PT_PER_MM;
+    let date = today();
+    let version = format!("{QRSTREAM_MAGIC}/{QRSTREAM_VERSION}");
+    let sheet_labels = sheets.iter().any(|sheet| sheet.label.is_some());
+    let caption_lines = 2 + options.label.iter().count() + usize::from(sheet_labels);
+
+    // cells are sized for the largest code, quiet zone included, with the caption below it
+    let max_modules = sheets
+        .iter()
+        .flat_map(|sheet| &sheet.codes)
+        .map(|(_, qr)| qr.width())
+        .max()
+        .unwrap_or(0);
+    let cell_width = (max_modules + 2 * QUIET_ZONE) as f32 * module;
+    let cell_height = cell_width + caption_lines as f32 * LINE_HEIGHT;
+    let footer = footer_lines();
+    let footer_height = (footer.len() + 2) as f32 * LINE_HEIGHT;
+    let columns = ((page_width - 2.0 * MARGIN) / cell_width).floor() as usize;
+    let rows = ((page_height - 2.0 * MARGIN - footer_height) / cell_height).floor() as usize;
+    if columns == 0 || rows == 0 {
+        Err(err_value_validation(format!(
+            "QR codes don't fit on the page with {} mm modules, use a smaller --module-size",
+            options.module_size
+        )))?;
+    }
+    let per_page = columns * rows;
+    // the grid is centred horizontally
+    let left = (page_width - columns as f32 * cell_width) / 2.0;
+
+    // each page of each sheet, numbered within its sheet
+    let pages = sheets
+        .iter()
+        .flat_map(|sheet| {
+            let page_count = sheet.codes.len().div_ceil(per_page);
+            (0..page_count).map(move |page_idx| (sheet, page_idx, page_count))
+        })
+        .collect::<Vec<_>>();
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let font_id = Ref::new(3);
+    let info_id = Ref::new(4);
+    let page_ids = (0..pages.len())
+        .map(|idx| Ref::new(5 + 2 * idx as i32))
+        .collect::<Vec<_>>();
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id)
+        .kids(page_ids.iter().copied())
+        .count(pages.len() as i32);
+    pdf.type1_font(font_id)
+        .base_font(Name(b"Helvetica"))
+        .encoding_predefined(Name(b"WinAnsiEncoding"));
+    pdf.document_info(info_id)
+        .title(TextStr(
+            options.label.as_deref().unwrap_or(clap::crate_name!()),
+        ))
+        .creator(TextStr(clap::crate_name!()));
+
+    for ((sheet, page_idx, page_count), page_id) in pages.into_iter().zip(&page_ids) {
+        let content_id = Ref::new(page_id.get() + 1);
+        let mut content = Content::new();
+        let page_qrs = sheet
+            .codes
+            .chunks(per_page)
+            .nth(page_idx)
+            .unwrap_or_default();
+        for (idx, (_, qr)) in page_qrs.iter().enumerate() {
+            let x = left + (idx % columns) as f32 * cell_width;
+            let top = page_height - MARGIN - (idx / columns) as f32 * cell_height;
+            draw_qr(&mut content, qr, x, top, module);
+
+            let part_idx = page_idx * per_page + idx;
+            let mut caption = vec![format!("part {} of {}", part_idx + 1, sheet.codes.len())];
+            caption.extend(sheet.label.clone());
+            caption.extend(options.label.clone());
+            caption.push(format!("{date}, format {version}"));
+            let text_x = x + QUIET_ZONE as f32 * module;
+            for (line_idx, line) in caption.iter().enumerate() {
+                let y = top - cell_width - FONT_SIZE - line_idx as f32 * LINE_HEIGHT;
+                draw_text(&mut content, line, text_x, y);
+            }
+        }
+        let mut footer = footer.clone();
+        footer.push(format!(
+            "Page {} of {page_count}{}, printed {date}, format {version}",
+            page_idx + 1,
+            sheet
+                .label
+                .as_ref()
+                .map(|label| format!(", {label}"))
+                .unwrap_or_default()
+        ));
+        for (line_idx, line) in footer.iter().enumerate() {
+            let y = MARGIN + (footer.len() - 1 - line_idx) as f32 * LINE_HEIGHT;
+            draw_text(&mut content, line, MARGIN, y);
+        }
+
+        let mut page = pdf.page(*page_id);
+        page.parent(page_tree_id)
+            .media_box(Rect::new(0.0, 0.0, page_width, page_height))
+            .contents(content_id);
+        page.resources().fonts().pair(FONT, font_id);
+        page.finish();
+        pdf.stream(content_id, &content.finish());
+    }
+
+    out.write_all(&pdf.finish())?;
+    Ok(())
+}
+
+/// Draws `qr` with its top left corner, quiet zone included, at `x`, `top`. Adjacent dark
+/// modules are joined into a single rectangle.
+fn draw_qr(content: &mut Content, qr: &QrCode, x: f32, top: f32, module: f32) {
+    let qr_size = qr.width();
+    for (row_idx, row) in qr.to_vec().chunks(qr_size).enumerate() {
+        let y = top - (QUIET_ZONE + row_idx + 1) as f32 * module;
+        let mut col_idx = 0;
+        while col_idx < qr_size {
+            if !row[col_idx] {
+                col_idx += 1;
+                continue;
+            }
+            let run = row[col_idx..].iter().take_while(|val| **val).count();
+            content.rect(
+                x + (QUIET_ZONE + col_idx) as f32 * module,
+                y,
+                run as f32 * module,
+                module,
+            );
+            col_idx += run;
+        }
+    }
+    content.fill_nonzero();
+}
+
+/// Draws a line of text with its baseline starting at `x`, `y`.
+fn draw_text(content: &mut Content, text: &str, x: f32, y: f32) {
+    // WinAnsiEncoding matches Latin-1 for the printable characters above ASCII
+    let encoded = text
+        .chars()
+        .map(|c| match u8::try_from(c) {
+            Ok(b) if (b' '..=b'~').contains(&b) || b >= 0xa0 => b,
+            _ => b'?',
+        })
+        .collect::<Vec<_>>();
+    content
+        .begin_text()
+        .set_font(FONT, FONT_SIZE)
+        .next_line(x, y)
+        .show(Str(&encoded))
+        .end_text();
+}
+
+/// Instructions printed at the bottom of every page.
+fn footer_lines() -> Vec<String> {
+    vec![
+        "To restore, install qrstream (https://github.com/amodm/qrstream-rs), then either show these codes to a webcam,".to_string(),
+        "in any order, with `qrstream -i camera decode > outfile`, or scan the pages to images and decode them with".to_string(),
+        "`qrstream -i page-1.png -i page-2.png ... decode > outfile`. Encrypted codes ask for their password, or need".to_string(),
+        "`--identity <file>` after `decode` if they were encrypted to an age key.".to_string(),
+    ]
+}
+
+/// Returns the current UTC date, as `YYYY-MM-DD`.
+fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    // days since the epoch to a civil date, as in Howard Hinnant's `civil_from_days`
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year:04}-{month:02}-{day:02}")
+}
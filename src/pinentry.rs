@@ -0,0 +1,106 @@
+//! Password entry through a pinentry program, over the Assuan protocol. It works without a
+//! terminal, e.g. when run from a GUI launcher, cron or a systemd unit.
+
+use crate::error::{err_value_validation, Result};
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use zeroize::Zeroizing;
+
+/// Program used if none is given
+pub(crate) const DEFAULT_PROGRAM: &str = "pinentry";
+
+/// Asks for a password with `program`, showing `description` to tell what it's needed for.
+pub(crate) fn get_pin(program: &str, description: &str) -> Result<String> {
+    let mut child = Command::new(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| err_value_validation(format!("cannot run {program}: {e}")))?;
+    let mut input = child.stdin.take().expect("stdin is piped");
+    let mut output = child.stdout.take().expect("stdout is piped");
+
+    // the server greets first
+    read_response(&mut output)?;
+    for command in [
+        format!("SETTITLE {}", clap::crate_name!()),
+        format!("SETDESC {}", escape(description)),
+        "SETPROMPT Password:".to_string(),
+    ] {
+        writeln!(input, "{command}")?;
+        read_response(&mut output)?;
+    }
+    writeln!(input, "GETPIN")?;
+    let pin = read_response(&mut output)?;
+    _ = writeln!(input, "BYE");
+    drop(input);
+    _ = child.wait();
+    // pinentry sends no data when the password is left empty, which is never meant as one
+    let pin = pin
+        .filter(|pin| !pin.is_empty())
+        .ok_or_else(|| err_value_validation("pinentry sent no password"))?;
+    let pin =
+        std::str::from_utf8(&pin).map_err(|_| err_value_validation("invalid pinentry data"))?;
+    Ok(pin.to_string())
+}
+
+/// Reads the lines of a response up to the one ending it, returning the data it carried, if any.
+fn read_response(output: &mut impl Read) -> Result<Option<Zeroizing<Vec<u8>>>> {
+    let mut data: Option<Zeroizing<Vec<u8>>> = None;
+    loop {
+        let line = read_line(output)?;
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches('\r');
+        if line == "OK" || line.starts_with("OK ") {
+            return Ok(data);
+        } else if let Some(error) = line.strip_prefix("ERR ") {
+            Err(err_value_validation(format!("pinentry error: {error}")))?;
+        } else if let Some(line_data) = line.strip_prefix("D ") {
+            data.get_or_insert_with(|| Zeroizing::new(Vec::with_capacity(256)))
+                .extend(unescape(line_data)?.iter());
+        }
+        // status and comment lines are of no use here
+    }
+}
+
+/// Reads a line, without its newline. It's read a byte at a time, as a buffered reader would
+/// leave a copy of the password behind.
+fn read_line(output: &mut impl Read) -> Result<Zeroizing<Vec<u8>>> {
+    let mut line = Zeroizing::new(Vec::<u8>::with_capacity(256));
+    let mut byte = [0u8; 1];
+    loop {
+        if output.read(&mut byte)? == 0 {
+            Err(err_value_validation("pinentry closed the connection"))?;
+        } else if byte[0] == b'\n' {
+            return Ok(line);
+        }
+        line.push(byte[0]);
+    }
+}
+
+/// Escapes the characters that can't appear as is in a command line.
+fn escape(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Reverses the percent escaping of data lines.
+fn unescape(s: &str) -> Result<Zeroizing<Vec<u8>>> {
+    let mut data = Zeroizing::new(Vec::<u8>::with_capacity(s.len()));
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = [bytes.next(), bytes.next()]
+                .into_iter()
+                .collect::<Option<Vec<_>>>()
+                .and_then(|hex| String::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(&hex, 16).ok())
+                .ok_or_else(|| err_value_validation("invalid pinentry data"))?;
+            data.push(hex);
+        } else {
+            data.push(b);
+        }
+    }
+    Ok(data)
+}
@@ -0,0 +1,118 @@
+//! Public key encryption to X25519 recipients, using age keys (`age1...` recipients and
+//! `AGE-SECRET-KEY-1...` identities). The key of the payload is random, and is wrapped for each
+//! recipient the way age wraps its file key: with a key derived from an ephemeral X25519 key
+//! agreement, using ChaCha20-Poly1305.
+
+use crate::error::{err_value_validation, Result};
+use crate::memory::SecretKey;
+use crate::ClapResult;
+use aes_gcm::aead::rand_core::OsRng;
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, KeyInit, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+const RECIPIENT_HRP: &str = "age";
+const IDENTITY_HRP: &str = "age-secret-key-";
+const WRAP_INFO: &[u8] = b"age-encryption.org/v1/X25519";
+
+/// Maximum number of recipients of a stream
+pub(crate) const MAX_RECIPIENTS: usize = 16;
+
+/// Length of a wrapped key, i.e. the ephemeral share followed by the sealed key and its tag
+const WRAPPED_KEY_LEN: usize = 32 + 32 + 16;
+
+/// Public key a stream can be encrypted to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Recipient(PublicKey);
+
+impl Recipient {
+    pub(crate) fn parse(s: &str) -> ClapResult<Self> {
+        let invalid_recipient = || err_value_validation(format!("invalid recipient {s}"));
+        let (hrp, data) = bech32::decode(s).map_err(|_| invalid_recipient())?;
+        if hrp.to_lowercase() != RECIPIENT_HRP {
+            Err(invalid_recipient())?;
+        }
+        let key: [u8; 32] = data.try_into().map_err(|_| invalid_recipient())?;
+        Ok(Self(PublicKey::from(key)))
+    }
+
+    /// Wraps `key` so that only the holder of the matching identity can unwrap it.
+    pub(crate) fn wrap(&self, key: &[u8; 32]) -> Result<Vec<u8>> {
+        let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+        let share = PublicKey::from(&ephemeral);
+        let shared = ephemeral.diffie_hellman(&self.0);
+        if !shared.was_contributory() {
+            Err(err_value_validation("invalid recipient"))?;
+        }
+        let sealed = wrap_cipher(shared.as_bytes(), &share, &self.0)
+            .encrypt(&Nonce::default(), key.as_slice())?;
+        let mut wrapped = share.as_bytes().to_vec();
+        wrapped.extend(sealed);
+        Ok(wrapped)
+    }
+}
+
+/// Private key matching a [Recipient].
+pub(crate) struct Identity(StaticSecret);
+
+impl Identity {
+    /// Reads the identities in an age identity file, skipping blank lines and comments.
+    pub(crate) fn read_file(path: &str) -> Result<Vec<Self>> {
+        let content = Zeroizing::new(std::fs::read_to_string(path)?);
+        let identities = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(Self::parse)
+            .collect::<Result<Vec<_>>>()?;
+        if identities.is_empty() {
+            Err(err_value_validation(format!("no identity in {path}")))?;
+        }
+        Ok(identities)
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        let invalid_identity = || err_value_validation("invalid identity");
+        let (hrp, data) = bech32::decode(s).map_err(|_| invalid_identity())?;
+        let data = Zeroizing::new(data);
+        if hrp.to_lowercase() != IDENTITY_HRP {
+            Err(invalid_identity())?;
+        }
+        let key: [u8; 32] = data.as_slice().try_into().map_err(|_| invalid_identity())?;
+        Ok(Self(StaticSecret::from(key)))
+    }
+
+    /// Reverses [Recipient::wrap], returning `None` if `wrapped` isn't meant for this identity.
+    pub(crate) fn unwrap(&self, wrapped: &[u8]) -> Option<SecretKey> {
+        if wrapped.len() != WRAPPED_KEY_LEN {
+            return None;
+        }
+        let (share, sealed) = wrapped.split_at(32);
+        let share = PublicKey::from(<[u8; 32]>::try_from(share).ok()?);
+        let shared = self.0.diffie_hellman(&share);
+        if !shared.was_contributory() {
+            return None;
+        }
+        let public = PublicKey::from(&self.0);
+        let key = Zeroizing::new(
+            wrap_cipher(shared.as_bytes(), &share, &public)
+                .decrypt(&Nonce::default(), sealed)
+                .ok()?,
+        );
+        SecretKey::from_slice(&key)
+    }
+}
+
+/// Cipher wrapping the key for a recipient. Its key is only ever used once, as the share is
+/// ephemeral, so a fixed nonce is fine.
+fn wrap_cipher(shared: &[u8; 32], share: &PublicKey, recipient: &PublicKey) -> ChaCha20Poly1305 {
+    let mut salt = share.as_bytes().to_vec();
+    salt.extend_from_slice(recipient.as_bytes());
+    let mut wrap_key = SecretKey::new();
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(WRAP_INFO, wrap_key.as_mut_slice())
+        .expect("wrap key length is valid for hkdf");
+    ChaCha20Poly1305::new((&*wrap_key).into())
+}
@@ -0,0 +1,64 @@
+//! Shamir's secret sharing over GF(256), with each byte of the secret shared on its own. Any
+//! `threshold` of the shares can be combined back into the secret, while fewer tell nothing
+//! about it.
+
+use aes_gcm::aead::rand_core::{OsRng, RngCore};
+use reed_solomon_erasure::galois_8::{add, div, mul};
+
+/// Maximum number of shares, as each needs its own non-zero x coordinate
+pub(crate) const MAX_SHARES: usize = 255;
+
+/// Splits `secret` into `count` shares, any `threshold` of which can be combined back into it.
+/// Each share is returned along with its x coordinate, `1..=count`. `threshold` must be at
+/// least 2.
+pub(crate) fn split(secret: &[u8], count: usize, threshold: usize) -> Vec<(u8, Vec<u8>)> {
+    // random coefficients of a polynomial of degree threshold - 1 per byte, whose constant
+    // term is the byte itself
+    let degree = threshold - 1;
+    let mut coefficients = vec![0u8; secret.len() * degree];
+    OsRng.fill_bytes(&mut coefficients);
+    (1..=count as u8)
+        .map(|x| {
+            let share = secret
+                .iter()
+                .zip(coefficients.chunks(degree))
+                .map(|(byte, coefficients)| {
+                    // Horner's method, from the highest degree down to the constant term
+                    let higher = coefficients
+                        .iter()
+                        .rev()
+                        .fold(0, |acc, c| add(mul(acc, x), *c));
+                    add(mul(higher, x), *byte)
+                })
+                .collect();
+            (x, share)
+        })
+        .collect()
+}
+
+/// Combines shares with distinct x coordinates back into the secret, by interpolating their
+/// polynomials at 0. This only gives back the secret if there are at least as many shares as
+/// the threshold they were split with.
+pub(crate) fn combine(shares: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let len = shares.iter().map(|(_, s)| s.len()).min().unwrap_or(0);
+    // Lagrange basis polynomials at 0. Subtraction is the same as addition in GF(256)
+    let weights = shares
+        .iter()
+        .map(|(xj, _)| {
+            shares
+                .iter()
+                .filter(|(xm, _)| xm != xj)
+                .fold(1, |acc, (xm, _)| mul(acc, div(*xm, add(*xm, *xj))))
+        })
+        .collect::<Vec<_>>();
+    (0..len)
+        .map(|i| {
+            shares
+                .iter()
+                .zip(&weights)
+                .fold(0, |acc, ((_, share), weight)| {
+                    add(acc, mul(*weight, share[i]))
+                })
+        })
+        .collect()
+}
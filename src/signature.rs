@@ -0,0 +1,33 @@
+//! Ed25519 signatures of streams, so that the origin of a stream can be verified on decoding.
+//! Keys are read from PEM files, as generated by e.g.
+//! `openssl genpkey -algorithm ed25519` and `openssl pkey -pubout`.
+
+use crate::error::{err_value_validation, Result};
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+
+/// Reads a PKCS#8 PEM private key from `path`.
+pub(crate) fn read_signing_key(path: &str) -> Result<SigningKey> {
+    let pem = std::fs::read_to_string(path)?;
+    Ok(SigningKey::from_pkcs8_pem(&pem)
+        .map_err(|_| err_value_validation(format!("invalid signing key in {path}")))?)
+}
+
+/// Reads a PEM public key from `path`.
+pub(crate) fn read_verifying_key(path: &str) -> Result<VerifyingKey> {
+    let pem = std::fs::read_to_string(path)?;
+    Ok(VerifyingKey::from_public_key_pem(&pem)
+        .map_err(|_| err_value_validation(format!("invalid verifying key in {path}")))?)
+}
+
+/// Verifies `signature` of `data` with `key`.
+pub(crate) fn verify(key: &VerifyingKey, data: &[u8], signature: &[u8]) -> Result<()> {
+    if signature.is_empty() {
+        Err(err_value_validation("stream is not signed"))?;
+    }
+    let signature =
+        Signature::from_slice(signature).map_err(|_| err_value_validation("invalid signature"))?;
+    key.verify_strict(data, &signature)
+        .map_err(|_| err_value_validation("signature verification failed"))?;
+    Ok(())
+}
@@ -0,0 +1,118 @@
+//! Structured Append, as per ISO/IEC 18004. Each QR code of a set carries its position in the
+//! set, the number of codes in it, and a parity byte of the whole data, so that any standard
+//! scanner can tell the codes belong together, and in which order.
+//!
+//! Each code of a qrstream set still carries a complete part, ending with a newline, so that
+//! the concatenated data is the same as the `txt` output.
+
+use crate::error::{err_value_validation, Result};
+use crate::QRSTREAM_MAGIC;
+use qr_code::bits::{Bits, ExtendedMode};
+use qr_code::types::QrError;
+use qr_code::{EcLevel, Version};
+use rxing::common::BitMatrix;
+use rxing::qrcode::cpp_port::decoder::Decode;
+
+/// Maximum number of codes in a set, as the position and the count are 4 bits each
+pub(crate) const MAX_CODES: usize = 16;
+
+/// A code read from a Structured Append set.
+pub(crate) struct Part {
+    index: usize,
+    total: usize,
+    parity: u8,
+    content: String,
+}
+
+/// Parity byte of a set, i.e. the XOR of all bytes of the data of all its codes.
+pub(crate) fn parity<'a>(contents: impl IntoIterator<Item = &'a [u8]>) -> u8 {
+    contents
+        .into_iter()
+        .flatten()
+        .fold(0, |parity, byte| parity ^ byte)
+}
+
+/// Encodes `data` as code `index` of a set of `total`, for the smallest QR code it fits in. The
+/// bits are turned into the code with [qr_code::QrCode::with_bits].
+pub(crate) fn encode(
+    data: &[u8],
+    index: usize,
+    total: usize,
+    parity: u8,
+    level: EcLevel,
+) -> Result<Bits> {
+    for version in 1..=40 {
+        let mut bits = Bits::new(Version::Normal(version));
+        bits.push_mode_indicator(ExtendedMode::StructuredAppend)?;
+        bits.push_number_checked(4, index)?;
+        bits.push_number_checked(4, total - 1)?;
+        bits.push_number_checked(8, parity as usize)?;
+        match bits
+            .push_optimal_data(data)
+            .and_then(|_| bits.push_terminator(level))
+        {
+            Ok(()) => return Ok(bits),
+            Err(QrError::DataTooLong) => continue,
+            Err(e) => Err(e)?,
+        }
+    }
+    Err(QrError::DataTooLong)?
+}
+
+/// Reads a Structured Append code from a grid that has been located in an image. Returns `None`
+/// if it can't be read, or isn't part of a set.
+pub(crate) fn read(grid: &impl rqrr::BitGrid) -> Option<Part> {
+    let size = grid.size() as u32;
+    let mut bits = BitMatrix::new(size, size).ok()?;
+    for y in 0..size {
+        for x in 0..size {
+            if grid.bit(y as usize, x as usize) {
+                bits.set(x, y);
+            }
+        }
+    }
+    let result = Decode(&bits).ok().filter(|r| r.isValid())?;
+    let info = result.structuredAppend();
+    if info.index < 0 || info.count < 1 {
+        return None;
+    }
+    Some(Part {
+        index: info.index as usize,
+        total: info.count as usize,
+        parity: info.id.parse().ok()?,
+        content: result.text(),
+    })
+}
+
+/// Combines the data of the codes of each complete set in `parts`, checking it against the
+/// parity of the set. Codes of incomplete sets are returned as is if they carry complete parts,
+/// as those from qrstream do, so that parity parts may make up for the missing ones.
+pub(crate) fn merge(mut parts: Vec<Part>) -> Result<Vec<String>> {
+    parts.sort_by_key(|p| (p.parity, p.total, p.index));
+    parts.dedup_by_key(|p| (p.parity, p.total, p.index));
+    let mut merged = Vec::<String>::new();
+    for set in parts.chunk_by(|a, b| (a.parity, a.total) == (b.parity, b.total)) {
+        if set.len() == set[0].total {
+            let content = set.iter().map(|p| p.content.as_str()).collect::<String>();
+            if parity([content.as_bytes()]) != set[0].parity {
+                Err(err_value_validation("structured append parity mismatch"))?;
+            }
+            merged.push(content);
+        } else if set.iter().all(|p| is_complete(&p.content)) {
+            merged.extend(set.iter().map(|p| p.content.clone()));
+        } else {
+            Err(err_value_validation(format!(
+                "incomplete structured append set: {} of {} codes",
+                set.len(),
+                set[0].total
+            )))?;
+        }
+    }
+    Ok(merged)
+}
+
+/// Whether `content` is made of complete parts, rather than a piece of a set split at an
+/// arbitrary point by another tool.
+fn is_complete(content: &str) -> bool {
+    content.starts_with(QRSTREAM_MAGIC) && content.ends_with('\n')
+}
@@ -0,0 +1,207 @@
+//! Terminal output. QR codes are drawn with Unicode half blocks, two rows of modules to a line,
+//! or as images with the kitty graphics protocol or sixel, for terminals that support them.
+
+use crate::console;
+use crate::encode::{render_grid, write_as_png, Sheet, QUIET_ZONE};
+use crate::error::{err_value_validation, Result};
+use crate::{ClapResult, EncodeOptions};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use qr_code::QrCode;
+use std::io::Write;
+
+/// Moves to the top left, and clears the screen and the scrollback
+const CLEAR: &str = "\x1b[H\x1b[2J\x1b[3J";
+/// Deletes all the images shown with the kitty graphics protocol, which clearing leaves alone
+const KITTY_DELETE_ALL: &str = "\x1b_Ga=d\x1b\\";
+/// Black modules on a white background, whatever the colours of the terminal
+const BLACK_ON_WHITE: &str = "\x1b[38;5;16;48;5;231m";
+const RESET: &str = "\x1b[0m";
+/// Largest payload of a kitty graphics protocol escape
+const KITTY_CHUNK_LEN: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TermGraphics {
+    Blocks,
+    Kitty,
+    Sixel,
+}
+
+impl TermGraphics {
+    pub(crate) fn parse(s: &str) -> ClapResult<Self> {
+        match s {
+            "blocks" => Ok(Self::Blocks),
+            "kitty" => Ok(Self::Kitty),
+            "sixel" => Ok(Self::Sixel),
+            _ => Err(err_value_validation(format!(
+                "invalid terminal graphics {s}"
+            ))),
+        }
+    }
+}
+
+/// Draws the codes one after the other, each followed by its part number. With
+/// `--one-at-a-time`, only one code is on the screen at any time, and the screen is cleared
+/// once done.
+pub(crate) fn write_as_term(
+    qr_vec: &[(String, QrCode)],
+    options: &EncodeOptions,
+    out: &mut impl Write,
+) -> Result<()> {
+    let graphics = options.term_graphics;
+    if !options.one_at_a_time {
+        for (idx, (_, qr)) in qr_vec.iter().enumerate() {
+            draw(qr, graphics, out)?;
+            writeln!(out, "part {} of {}", idx + 1, qr_vec.len())?;
+            writeln!(out)?;
+        }
+        return Ok(());
+    }
+
+    if !console::has_tty() {
+        Err(err_value_validation(
+            "showing QR codes one at a time needs a terminal",
+        ))?;
+    }
+    let result = show_one_at_a_time(qr_vec, graphics, out);
+    // nothing is left behind on the screen, even if showing the codes failed
+    clear(graphics, out)?;
+    result
+}
+
+fn show_one_at_a_time(
+    qr_vec: &[(String, QrCode)],
+    graphics: TermGraphics,
+    out: &mut impl Write,
+) -> Result<()> {
+    for (idx, (_, qr)) in qr_vec.iter().enumerate() {
+        clear(graphics, out)?;
+        draw(qr, graphics, out)?;
+        let next = if idx + 1 < qr_vec.len() {
+            "show the next part, or q to stop"
+        } else {
+            "clear the screen"
+        };
+        write!(
+            out,
+            "part {} of {}, press a key to {next}",
+            idx + 1,
+            qr_vec.len()
+        )?;
+        out.flush()?;
+        // q, Esc and Ctrl-C stop early
+        if matches!(console::read_key()?, b'q' | b'Q' | 0x1b | 0x03) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn clear(graphics: TermGraphics, out: &mut impl Write) -> Result<()> {
+    if graphics == TermGraphics::Kitty {
+        write!(out, "{KITTY_DELETE_ALL}")?;
+    }
+    write!(out, "{CLEAR}")?;
+    out.flush()?;
+    Ok(())
+}
+
+fn draw(qr: &QrCode, graphics: TermGraphics, out: &mut impl Write) -> Result<()> {
+    match graphics {
+        TermGraphics::Blocks => draw_blocks(qr, out),
+        TermGraphics::Kitty => draw_kitty(qr, out),
+        TermGraphics::Sixel => draw_sixel(qr, out),
+    }
+}
+
+/// Draws `qr` with its quiet zone, each character covering a module and the one below it.
+fn draw_blocks(qr: &QrCode, out: &mut impl Write) -> Result<()> {
+    let qr_size = qr.width();
+    let modules = qr.to_vec();
+    let size = qr_size + 2 * QUIET_ZONE;
+    let is_dark = |x: usize, y: usize| {
+        (QUIET_ZONE..QUIET_ZONE + qr_size).contains(&x)
+            && (QUIET_ZONE..QUIET_ZONE + qr_size).contains(&y)
+            && modules[(y - QUIET_ZONE) * qr_size + x - QUIET_ZONE]
+    };
+    for y in (0..size).step_by(2) {
+        let line = (0..size)
+            .map(|x| match (is_dark(x, y), is_dark(x, y + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            })
+            .collect::<String>();
+        writeln!(out, "{BLACK_ON_WHITE}{line}{RESET}")?;
+    }
+    Ok(())
+}
+
+/// Draws `qr` as a PNG, sent in base64 chunks.
+fn draw_kitty(qr: &QrCode, out: &mut impl Write) -> Result<()> {
+    let mut png = Vec::<u8>::new();
+    let sheet = Sheet {
+        label: None,
+        codes: vec![(String::new(), qr.clone())],
+    };
+    write_as_png(&[sheet], 1, &mut png)?;
+    let encoded = STANDARD.encode(png);
+    let chunks = encoded
+        .as_bytes()
+        .chunks(KITTY_CHUNK_LEN)
+        .collect::<Vec<_>>();
+    for (idx, chunk) in chunks.iter().enumerate() {
+        // all but the last chunk tell that more follow
+        let more = u8::from(idx + 1 < chunks.len());
+        if idx == 0 {
+            write!(out, "\x1b_Ga=T,f=100,m={more};")?;
+        } else {
+            write!(out, "\x1b_Gm={more};")?;
+        }
+        out.write_all(chunk)?;
+        write!(out, "\x1b\\")?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Draws `qr` in sixels, i.e. columns of 6 pixels, one band of 6 rows at a time. Each band is
+/// drawn once in white and once in black, with runs of the same sixel compressed.
+fn draw_sixel(qr: &QrCode, out: &mut impl Write) -> Result<()> {
+    let img = render_grid(&[(String::new(), qr.clone())], 1);
+    let (width, height) = img.dimensions();
+    write!(
+        out,
+        "\x1bPq\"1;1;{width};{height}#0;2;100;100;100#1;2;0;0;0"
+    )?;
+    for band in (0..height).step_by(6) {
+        for color in [0, 1] {
+            let sixels = (0..width).map(|x| {
+                let bits = (0..6)
+                    .filter(|dy| band + dy < height)
+                    .filter(|dy| (img.get_pixel(x, band + dy)[0] < 128) == (color == 1))
+                    .fold(0, |acc, dy| acc | 1 << dy);
+                (b'?' + bits) as char
+            });
+            write!(out, "#{color}")?;
+            let mut sixels = sixels.peekable();
+            while let Some(sixel) = sixels.next() {
+                let mut run = 1;
+                while sixels.next_if_eq(&sixel).is_some() {
+                    run += 1;
+                }
+                if run > 3 {
+                    write!(out, "!{run}{sixel}")?;
+                } else {
+                    write!(out, "{}", sixel.to_string().repeat(run))?;
+                }
+            }
+            // back to the start of the band, for the other color
+            write!(out, "$")?;
+        }
+        write!(out, "-")?;
+    }
+    write!(out, "\x1b\\")?;
+    writeln!(out)?;
+    Ok(())
+}
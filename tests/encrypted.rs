@@ -1,13 +1,22 @@
 mod common;
-use common::{decode, encode, rand_password_key, QRSTREAM_MAGIC, QRSTREAM_VERSION};
+use assert_cmd::cargo::CommandCargoExt;
+use assert_cmd::Command;
+use common::{
+    decode, decode_failure, decode_failure_with_args, decode_with_args, encode, encode_with_args,
+    header_entry, rand_password_key, rand_text, single_part_payload, temp_dir,
+    with_single_part_payload, QRSTREAM_CMD, QRSTREAM_MAGIC, QRSTREAM_VERSION, SIGNING_KEY,
+    VERIFYING_KEY,
+};
 use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
+use std::os::unix::fs::PermissionsExt;
 
 #[test]
 fn test_format_txt_encode() -> Result<(), Box<dyn std::error::Error>> {
     let password = &RAND_PASSWORD;
     let data = "Hello World";
     let stdout = String::from_utf8(encode(data, "txt", password)?)?;
-    assert!(stdout.starts_with(&format!("{QRSTREAM_MAGIC}/{QRSTREAM_VERSION};p=11;t=")));
+    assert!(stdout.starts_with(&format!("{QRSTREAM_MAGIC}/{QRSTREAM_VERSION};p=1/1;c=")));
 
     Ok(())
 }
@@ -42,6 +51,644 @@ fn test_format_png_decode() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn test_password_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let password = Some(rand_text(13));
+    let data = "Hello World";
+    let encrypted = encode(data, "txt", &password)?;
+    let decoded = decode(&encrypted, &password)?;
+    assert_eq!(data, decoded);
+
+    Ok(())
+}
+
+#[test]
+fn test_argon2id_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let password = Some(rand_text(13));
+    let data = "Hello World";
+    let kdf_args = ["-o", "txt", "--kdf", "argon2id:m=1024,t=1,p=1"];
+    let encrypted = encode_with_args(data, &kdf_args, &password)?;
+    let decoded = decode(&encrypted, &password)?;
+    assert_eq!(data, decoded);
+
+    Ok(())
+}
+
+#[test]
+fn test_random_salt() -> Result<(), Box<dyn std::error::Error>> {
+    let password = Some("hunter2".to_string());
+    let data = "Hello World";
+    let kdf_args = ["-o", "txt", "--kdf", "argon2id:m=1024,t=1,p=1"];
+    let salts = [(); 2].map(|_| {
+        let payload = single_part_payload(&encode_with_args(data, &kdf_args, &password).unwrap());
+        payload[header_entry(&payload, TAG_SALT).unwrap()].to_vec()
+    });
+    assert_eq!(16, salts[0].len());
+    assert_ne!(salts[0], salts[1]);
+
+    Ok(())
+}
+
+#[test]
+fn test_pbkdf2_limits_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let password = Some("hunter2".to_string());
+    let data = "Hello World";
+    let stderr = Command::cargo_bin(QRSTREAM_CMD)?
+        .args(["-p", "hunter2", "encode", "--kdf", "pbkdf2:i=4000000000"])
+        .write_stdin(data)
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .to_owned();
+    assert!(String::from_utf8(stderr)?.contains("exceed the limit"));
+
+    // a forged header asking for too many iterations is refused before deriving the key
+    let encrypted = encode_with_args(data, &["-o", "txt", "--kdf", "pbkdf2:i=1000"], &password)?;
+    let mut payload = single_part_payload(&encrypted);
+    let kdf = header_entry(&payload, TAG_KDF).unwrap();
+    payload[kdf.start + 1..kdf.end].copy_from_slice(&u32::MAX.to_be_bytes());
+    let forged = with_single_part_payload(&encrypted, &payload);
+    let stderr = decode_failure(&forged, &password)?;
+    assert!(stderr.contains("exceed the limit"));
+
+    Ok(())
+}
+
+#[test]
+fn test_argon2id_limits_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let password = Some("hunter2".to_string());
+    let data = "Hello World";
+    let kdf_args = ["-o", "txt", "--kdf", "argon2id:m=1024,t=1,p=1"];
+    let encrypted = encode_with_args(data, &kdf_args, &password)?;
+    // forged headers asking for too much memory or time are refused before deriving the key
+    for (offset, value, error) in [
+        (0, 4_000_000, "memory cost 4000000 KiB exceeds"),
+        (4, 4_000_000_000, "time cost 4000000000 exceeds"),
+        (8, 1000, "parallelism 1000 exceeds"),
+    ] {
+        let mut payload = single_part_payload(&encrypted);
+        let param = header_entry(&payload, TAG_KDF).unwrap().start + 1 + offset;
+        payload[param..param + 4].copy_from_slice(&u32::to_be_bytes(value));
+        let forged = with_single_part_payload(&encrypted, &payload);
+        let stderr = decode_failure(&forged, &password)?;
+        assert!(stderr.contains(error));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_cipher_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let password = &RAND_PASSWORD;
+    let data = "Hello World";
+    for cipher in ["chacha20poly1305", "xchacha20poly1305"] {
+        let encrypted = encode_with_args(data, &["-o", "txt", "--cipher", cipher], password)?;
+        let decoded = decode(&encrypted, password)?;
+        assert_eq!(data, decoded);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_compressed_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let password = &RAND_PASSWORD;
+    let data = "Hello World\n".repeat(500);
+    let uncompressed = encode(&data, "txt", password)?;
+    for compress in ["deflate", "zstd", "auto"] {
+        let encrypted = encode_with_args(&data, &["-o", "txt", "--compress", compress], password)?;
+        assert!(encrypted.len() < uncompressed.len() / 4);
+        let decoded = decode(&encrypted, password)?;
+        assert_eq!(data, decoded);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_large_input_decode() -> Result<(), Box<dyn std::error::Error>> {
+    // large enough for the buffers holding the plaintext to grow, when read from a pipe, and
+    // when decompressed
+    let password = &RAND_PASSWORD;
+    let data = "Hello World\n".repeat(20_000);
+    let dir = temp_dir()?;
+    let input = dir.join("input.txt");
+    std::fs::write(&input, &data)?;
+    for compress in ["deflate", "zstd"] {
+        let args = ["-o", "txt", "--compress", compress];
+        let encrypted = encode_with_args(&data, &args, password)?;
+        assert_eq!(data, decode(&encrypted, password)?);
+        let encrypted = Command::cargo_bin(QRSTREAM_CMD)?
+            .args([
+                "-p",
+                password.as_deref().unwrap(),
+                "-i",
+                input.to_str().unwrap(),
+            ])
+            .arg("encode")
+            .args(args)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .to_owned();
+        assert_eq!(data, decode(&encrypted, password)?);
+    }
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_restore_name_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let password = RAND_PASSWORD.as_deref().unwrap();
+    let dir = temp_dir()?;
+    let data = "Hello World";
+    let source = dir.join("hello.txt");
+    std::fs::write(&source, data)?;
+    let source = source.to_str().unwrap();
+    let encrypted = Command::cargo_bin(QRSTREAM_CMD)?
+        .args([
+            "-p",
+            password,
+            "-i",
+            source,
+            "encode",
+            "-o",
+            "txt",
+            "--metadata",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .to_owned();
+    // without --restore-name, only the data itself is written out
+    assert_eq!(data, decode(&encrypted, &RAND_PASSWORD)?);
+
+    let restore_dir = dir.join("restored");
+    std::fs::create_dir(&restore_dir)?;
+    let restore = || {
+        Command::cargo_bin(QRSTREAM_CMD)
+            .unwrap()
+            .current_dir(&restore_dir)
+            .args(["-p", password, "decode", "--restore-name"])
+            .write_stdin(encrypted.clone())
+            .assert()
+    };
+    restore().success();
+    let restored = restore_dir.join("hello.txt");
+    assert_eq!(data, std::fs::read_to_string(&restored)?);
+    assert_eq!(
+        0o600,
+        std::fs::metadata(&restored)?.permissions().mode() & 0o777
+    );
+
+    let stderr = restore().failure().get_output().stderr.to_owned();
+    assert!(String::from_utf8(stderr)?.contains("refusing to overwrite"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_recipient_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = temp_dir()?;
+    let identity = dir.join("identity.txt");
+    std::fs::write(&identity, IDENTITY)?;
+    let other_identity = dir.join("other-identity.txt");
+    std::fs::write(&other_identity, OTHER_IDENTITY)?;
+    let data = "Hello World";
+    let encrypted = encode_with_args(
+        data,
+        &[
+            "-o",
+            "txt",
+            "--recipient",
+            RECIPIENT,
+            "--recipient",
+            OTHER_RECIPIENT,
+        ],
+        &None,
+    )?;
+    for identity in [&identity, &other_identity] {
+        let decoded = decode_with_args(
+            &encrypted,
+            &["--identity", identity.to_str().unwrap()],
+            &None,
+        )?;
+        assert_eq!(data, decoded);
+    }
+
+    let encrypted = encode_with_args(data, &["-o", "txt", "--recipient", RECIPIENT], &None)?;
+    let other_identity = ["--identity", other_identity.to_str().unwrap()];
+    let stderr = decode_failure_with_args(&encrypted, &other_identity, &None)?;
+    assert!(stderr.contains("isn't encrypted to this identity"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_key_slots_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = temp_dir()?;
+    let identity = dir.join("identity.txt");
+    std::fs::write(&identity, IDENTITY)?;
+    let keys = [1, 2, 3].map(|i| format!("key:{}", format!("{i:02x}").repeat(32)));
+    let data = "Hello World";
+    let encrypted = Command::cargo_bin(QRSTREAM_CMD)?
+        .args(["-p", &keys[0], "-p", &keys[1], "encode", "-o", "txt"])
+        .args(["--recipient", RECIPIENT])
+        .write_stdin(data)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .to_owned();
+    // any one of the passwords decrypts, as does the identity of the recipient
+    for key in &keys[..2] {
+        assert_eq!(data, decode(&encrypted, &Some(key.clone()))?);
+    }
+    let identity = ["--identity", identity.to_str().unwrap()];
+    assert_eq!(data, decode_with_args(&encrypted, &identity, &None)?);
+    let stderr = decode_failure(&encrypted, &Some(keys[2].clone()))?;
+    assert!(stderr.contains("incorrect password"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_key_slots_limit() -> Result<(), Box<dyn std::error::Error>> {
+    let keys = (0..17)
+        .map(|i| format!("key:{}", format!("{i:02x}").repeat(32)))
+        .collect::<Vec<_>>();
+    let data = "Hello World";
+    let mut args = keys.iter().flat_map(|key| ["-p", key]).collect::<Vec<_>>();
+    args.extend(["encode", "-o", "txt"]);
+    let stderr = Command::cargo_bin(QRSTREAM_CMD)?
+        .args(&args)
+        .write_stdin(data)
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .to_owned();
+    assert!(String::from_utf8(stderr)?.contains("too many passwords or recipients"));
+
+    // a forged header with a key slot repeated beyond the limit is refused, before any KDF runs
+    let password = Some(keys[0].clone());
+    let encrypted = Command::cargo_bin(QRSTREAM_CMD)?
+        .args(["-p", &keys[0], "-p", &keys[1], "encode", "-o", "txt"])
+        .write_stdin(data)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .to_owned();
+    let payload = single_part_payload(&encrypted);
+    let slot = header_entry(&payload, TAG_KEY_SLOT).unwrap();
+    let slot = &payload[slot.start - 3..slot.end];
+    let header_len = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+    let header_end = 2 + header_len;
+    let mut forged = ((header_len + 15 * slot.len()) as u16)
+        .to_be_bytes()
+        .to_vec();
+    forged.extend_from_slice(&payload[2..header_end]);
+    forged.extend(slot.repeat(15));
+    forged.extend_from_slice(&payload[header_end..]);
+    let forged = with_single_part_payload(&encrypted, &forged);
+    let stderr = decode_failure(&forged, &password)?;
+    assert!(stderr.contains("too many key slots or recipients"));
+
+    Ok(())
+}
+
+#[test]
+fn test_keyfile_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = temp_dir()?;
+    let keyfile = dir.join("keyfile.bin");
+    std::fs::write(&keyfile, rand_text(64))?;
+    let keyfile = Some(format!("file:{}", keyfile.to_str().unwrap()));
+    let data = "Hello World";
+    let encrypted = encode(data, "txt", &keyfile)?;
+    assert_eq!(data, decode(&encrypted, &keyfile)?);
+    let stderr = decode_failure(&encrypted, &RAND_PASSWORD)?;
+    assert!(stderr.contains("incorrect password"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_keyfile_with_password_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = temp_dir()?;
+    // a `+` in the path is only taken to combine the keyfile when followed by a source
+    let keyfile = dir.join("key+file.bin");
+    std::fs::write(&keyfile, rand_text(64))?;
+    let keyfile = keyfile.to_str().unwrap();
+    let run = |password: &str, env_password: &str, args: &[&str], stdin: &[u8]| {
+        Command::cargo_bin(QRSTREAM_CMD)
+            .unwrap()
+            .env("QRSTREAM_TEST_PASSWORD", env_password)
+            .args(["-p", password])
+            .args(args)
+            .write_stdin(stdin)
+            .assert()
+    };
+    let combined = format!("file:{keyfile}+env:QRSTREAM_TEST_PASSWORD");
+    let data = "Hello World";
+    let encode_args = ["encode", "-o", "txt", "--kdf", "argon2id:m=1024,t=1,p=1"];
+    let encrypted = run(&combined, "hunter2", &encode_args, data.as_bytes())
+        .success()
+        .get_output()
+        .stdout
+        .to_owned();
+    let decoded = run(&combined, "hunter2", &["decode"], &encrypted)
+        .success()
+        .get_output()
+        .stdout
+        .to_owned();
+    assert_eq!(data, String::from_utf8(decoded)?);
+
+    // both the keyfile and the password are needed
+    let keyfile_only = format!("file:{keyfile}");
+    for (password, env_password) in [
+        (keyfile_only.as_str(), ""),
+        ("env:QRSTREAM_TEST_PASSWORD", "hunter2"),
+        (combined.as_str(), "hunter3"),
+    ] {
+        let stderr = run(password, env_password, &["decode"], &encrypted)
+            .failure()
+            .get_output()
+            .stderr
+            .to_owned();
+        assert!(String::from_utf8(stderr)?.contains("incorrect password"));
+    }
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_fd_password_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = temp_dir()?;
+    let data = "Hello World";
+    let kdf = ["-o", "txt", "--kdf", "argon2id:m=1024,t=1,p=1"];
+    let encrypted = encode_with_args(data, &kdf, &Some("hunter2".into()))?;
+    let input = dir.join("encrypted.txt");
+    std::fs::write(&input, encrypted)?;
+    // the password is read from stdin, as the data comes from a file
+    let decoded = Command::cargo_bin(QRSTREAM_CMD)?
+        .args(["-p", "fd:0", "-i", input.to_str().unwrap(), "decode"])
+        .write_stdin("hunter2\n")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .to_owned();
+    assert_eq!(data, String::from_utf8(decoded)?);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_fd_password_left_open() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = temp_dir()?;
+    let input = dir.join("input.txt");
+    std::fs::write(&input, "Hello World")?;
+    let password = dir.join("password.txt");
+    std::fs::write(&password, "hunter2\n")?;
+    // the password command runs after fd:0 has been read, and fails unless qrstream still has
+    // the password file open as its stdin
+    let command = format!(
+        "cmd:test \"$(readlink /proc/$PPID/fd/0)\" = '{}' && echo hunter3",
+        password.display()
+    );
+    let output = std::process::Command::cargo_bin(QRSTREAM_CMD)?
+        .args(["-p", "fd:0", "-p", &command, "-i", input.to_str().unwrap()])
+        .args(["encode", "-o", "txt", "--kdf", "argon2id:m=1024,t=1,p=1"])
+        .stdin(std::fs::File::open(&password)?)
+        .output()?;
+    assert!(output.status.success());
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_command_password_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let data = "Hello World";
+    let kdf = ["-o", "txt", "--kdf", "argon2id:m=1024,t=1,p=1"];
+    let encrypted = encode_with_args(data, &kdf, &Some("cmd:echo ' hunter2 '".into()))?;
+    assert_eq!(data, decode(&encrypted, &Some("hunter2".into()))?);
+    let stderr = decode_failure(&encrypted, &Some("cmd:exit 3".into()))?;
+    assert!(stderr.contains("password command failed"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_core_dumps_disabled() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = temp_dir()?;
+    let limits = dir.join("limits.txt");
+    // the password command is a child of qrstream, so it can look at the limits of its parent
+    let command = format!(
+        "cmd:grep 'core file size' /proc/$PPID/limits > {}; echo hunter2",
+        limits.display()
+    );
+    let data = "Hello World";
+    let kdf = ["-o", "txt", "--kdf", "argon2id:m=1024,t=1,p=1"];
+    let encrypted = encode_with_args(data, &kdf, &Some(command))?;
+    assert_eq!(data, decode(&encrypted, &Some("hunter2".into()))?);
+    let limits = std::fs::read_to_string(&limits)?;
+    assert_eq!(
+        vec!["Max", "core", "file", "size", "0", "0", "bytes"],
+        limits.split_whitespace().collect::<Vec<_>>()
+    );
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_pinentry_password_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = temp_dir()?;
+    let pinentry = dir.join("pinentry");
+    let description = dir.join("description.txt");
+    std::fs::write(
+        &pinentry,
+        format!(
+            r#"#!/bin/sh
+echo "OK Pleased to meet you"
+while read -r command args; do
+    case "$command" in
+        SETDESC) echo "$args" > "{}"; echo OK ;;
+        GETPIN) echo "D hunter%252"; echo OK ;;
+        BYE) echo "OK closing connection"; exit 0 ;;
+        *) echo OK ;;
+    esac
+done
+"#,
+            description.display()
+        ),
+    )?;
+    std::fs::set_permissions(&pinentry, std::fs::Permissions::from_mode(0o755))?;
+    let pinentry_password = Some(format!("pinentry:{}", pinentry.display()));
+
+    let data = "Hello World";
+    let kdf = ["-o", "txt", "--kdf", "argon2id:m=1024,t=1,p=1"];
+    let encrypted = encode_with_args(data, &kdf, &pinentry_password)?;
+    assert_eq!(data, decode(&encrypted, &Some("hunter%2".into()))?);
+
+    let input = dir.join("stream.txt");
+    std::fs::write(&input, &encrypted)?;
+    let input = input.to_str().unwrap();
+    let decoded = Command::cargo_bin(QRSTREAM_CMD)?
+        .args([
+            "-p",
+            pinentry_password.as_ref().unwrap(),
+            "-i",
+            input,
+            "decode",
+        ])
+        .output()?;
+    assert!(decoded.status.success());
+    assert_eq!(data.as_bytes(), decoded.stdout);
+    let description = std::fs::read_to_string(&description)?;
+    assert!(description.contains(input));
+
+    let missing = Some("pinentry:/nonexistent/pinentry".to_string());
+    let stderr = decode_failure(&encrypted, &missing)?;
+    assert!(stderr.contains("cannot run /nonexistent/pinentry"));
+
+    // an empty password is answered with no data at all, and must not be used as ""
+    let empty_pinentry = dir.join("empty-pinentry");
+    std::fs::write(
+        &empty_pinentry,
+        r#"#!/bin/sh
+echo "OK Pleased to meet you"
+while read -r command args; do
+    case "$command" in
+        BYE) echo "OK closing connection"; exit 0 ;;
+        *) echo OK ;;
+    esac
+done
+"#,
+    )?;
+    std::fs::set_permissions(&empty_pinentry, std::fs::Permissions::from_mode(0o755))?;
+    let stderr = Command::cargo_bin(QRSTREAM_CMD)?
+        .args(["-p", &format!("pinentry:{}", empty_pinentry.display())])
+        .args(["encode", "-o", "txt"])
+        .write_stdin(data)
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .to_owned();
+    assert!(String::from_utf8(stderr)?.contains("pinentry sent no password"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_signed_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let password = &RAND_PASSWORD;
+    let dir = temp_dir()?;
+    let signing_key = dir.join("signing.pem");
+    std::fs::write(&signing_key, SIGNING_KEY)?;
+    let verifying_key = dir.join("verifying.pem");
+    std::fs::write(&verifying_key, VERIFYING_KEY)?;
+    let data = "Hello World";
+    let sign_args = ["-o", "txt", "--sign-key", signing_key.to_str().unwrap()];
+    let encrypted = encode_with_args(data, &sign_args, password)?;
+    let verify_args = ["--verify-key", verifying_key.to_str().unwrap()];
+    assert_eq!(data, decode_with_args(&encrypted, &verify_args, password)?);
+
+    let unsigned = encode(data, "txt", password)?;
+    let stderr = decode_failure_with_args(&unsigned, &verify_args, password)?;
+    assert!(stderr.contains("stream is not signed"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_incorrect_key_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let data = "Hello World";
+    let encrypted = encode(data, "txt", &RAND_PASSWORD)?;
+    let other_key = Some(format!("key:{}", "ab".repeat(32)));
+    let stderr = decode_failure(&encrypted, &other_key)?;
+    assert!(stderr.contains("incorrect password"));
+
+    Ok(())
+}
+
+#[test]
+fn test_tampered_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let data = "Hello World";
+    let encrypted = encode(data, "txt", &RAND_PASSWORD)?;
+    let payload = single_part_payload(&encrypted);
+
+    // the cipher is switched to ChaCha20-Poly1305, which has the same key and nonce sizes
+    let mut tampered = payload.clone();
+    tampered[header_entry(&payload, TAG_CIPHER).unwrap().start] = 2;
+    let forged = with_single_part_payload(&encrypted, &tampered);
+    let stderr = decode_failure(&forged, &RAND_PASSWORD)?;
+    assert!(stderr.contains("header or data tampered"));
+
+    // a modified body is caught the same way, even with the digest recomputed to match it
+    let mut tampered = payload.clone();
+    let digest = header_entry(&payload, TAG_DIGEST).unwrap();
+    let length = header_entry(&payload, TAG_LENGTH).unwrap();
+    let length = u32::from_be_bytes(payload[length].try_into()?) as usize;
+    let header_end = 2 + u16::from_be_bytes([payload[0], payload[1]]) as usize;
+    let body = header_end..header_end + length;
+    tampered[body.end - 1] ^= 1;
+    let recomputed = Sha256::digest(&tampered[body]);
+    tampered[digest].copy_from_slice(&recomputed);
+    let forged = with_single_part_payload(&encrypted, &tampered);
+    let stderr = decode_failure(&forged, &RAND_PASSWORD)?;
+    assert!(stderr.contains("header or data tampered"));
+
+    Ok(())
+}
+
+#[test]
+fn test_format_v1_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let encrypted = b"QRST/1;p=11;t=DGKzRl-B6jyJcaUOSGfvyXG-RjjYbCEvrKjQsMCwJgCbFTCN4Otx-g";
+    let key = Some("key:000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f".into());
+    let decoded = decode(encrypted, &key)?;
+    assert_eq!("Hello World", decoded);
+
+    Ok(())
+}
+
+/// Tags of the stream header entries
+const TAG_CIPHER: u8 = 1;
+const TAG_KDF: u8 = 2;
+const TAG_SALT: u8 = 3;
+const TAG_LENGTH: u8 = 4;
+const TAG_DIGEST: u8 = 6;
+const TAG_KEY_SLOT: u8 = 11;
+
+/// Identities generated with age, in the format of age-keygen
+const IDENTITY: &str =
+    "# public key: age1tm2myc4wjlchg2ywclaxl3rw6enejrd8e6rjtsnpdl3gxk3xc5mqc8gw3w
+AGE-SECRET-KEY-1HWWW2GZL6N28ZQ5P48PA3XLVUZDF0XUDUETL5S9NSG0FKYPZKU8S6XAFPY
+";
+const RECIPIENT: &str = "age1tm2myc4wjlchg2ywclaxl3rw6enejrd8e6rjtsnpdl3gxk3xc5mqc8gw3w";
+const OTHER_IDENTITY: &str =
+    "# public key: age1n79sunvwy3dq5cexl7yfz3kfskgsnj8anl0f6l594p4klf6sx94qqggey5
+AGE-SECRET-KEY-1GF7768UKDRDWPSQWPNYCMRDCJ0J73JMZJ6EP8AEUH8WU95GNTR6SEZMYM7
+";
+const OTHER_RECIPIENT: &str = "age1n79sunvwy3dq5cexl7yfz3kfskgsnj8anl0f6l594p4klf6sx94qqggey5";
+
 lazy_static! {
     static ref RAND_PASSWORD: Option<String> =
         Some(format!("key:{}", rand_password_key().unwrap()));
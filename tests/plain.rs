@@ -1,11 +1,19 @@
 mod common;
-use common::{decode, encode, QRSTREAM_MAGIC, QRSTREAM_VERSION};
+use assert_cmd::Command;
+use base64::Engine as _;
+use common::{
+    decode, decode_failure, decode_failure_with_args, decode_with_args, encode, encode_with_args,
+    rand_text, temp_dir, with_single_part_payload, OTHER_VERIFYING_KEY, QRSTREAM_CMD,
+    QRSTREAM_MAGIC, QRSTREAM_VERSION, SIGNING_KEY, VERIFYING_KEY,
+};
 
 #[test]
 fn test_format_txt_encode() -> Result<(), Box<dyn std::error::Error>> {
     let data = "Hello World";
     let stdout = String::from_utf8(encode(data, "txt", &None)?)?;
-    assert!(stdout.starts_with(&format!("{QRSTREAM_MAGIC}/{QRSTREAM_VERSION};p=11;t=")));
+    assert!(stdout.starts_with(&format!("{QRSTREAM_MAGIC}/{QRSTREAM_VERSION};p=1/1;c=")));
+    // base45 data, for the QR alphanumeric mode
+    assert!(stdout.trim_end().ends_with("%69 VD82EI2B.KEA2"));
 
     Ok(())
 }
@@ -36,3 +44,644 @@ fn test_format_png_decode() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn test_format_svg_encode() -> Result<(), Box<dyn std::error::Error>> {
+    let data = rand_text(3_000);
+    let lines = String::from_utf8(encode(&data, "txt", &None)?)?;
+    let parts = lines.lines().count();
+    let args = ["-o", "svg", "--qr-per-row", "2", "--captions"];
+    let svg = String::from_utf8(encode_with_args(&data, &args, &None)?)?;
+    assert!(svg.starts_with("<?xml"));
+    assert!(svg.trim_end().ends_with("</svg>"));
+    for part in 1..=parts {
+        assert!(svg.contains(&format!(">part {part} of {parts}</text>")));
+    }
+
+    // the rectangles drawn back into a PNG decode to the data
+    let attr = |line: &str, name: &str| -> u32 {
+        let value = line.split(&format!(" {name}=\"")).nth(1).unwrap();
+        value[..value.find('"').unwrap()].parse().unwrap()
+    };
+    let svg_tag = svg.lines().find(|line| line.starts_with("<svg")).unwrap();
+    let mut img = image::GrayImage::new(attr(svg_tag, "width"), attr(svg_tag, "height"));
+    img.fill(255);
+    for rect in svg.lines().filter(|line| line.starts_with("<rect x=")) {
+        let (x, y) = (attr(rect, "x"), attr(rect, "y"));
+        for dy in 0..attr(rect, "height") {
+            for dx in 0..attr(rect, "width") {
+                img.put_pixel(x + dx, y + dy, image::Luma([0]));
+            }
+        }
+    }
+    let mut png = std::io::Cursor::new(Vec::<u8>::new());
+    img.write_to(&mut png, image::ImageOutputFormat::Png)?;
+    assert_eq!(data, decode(png.get_ref(), &None)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_format_pdf_encode() -> Result<(), Box<dyn std::error::Error>> {
+    let data = rand_text(6_000);
+    let lines = String::from_utf8(encode(&data, "txt", &None)?)?;
+    let parts = lines.lines().count();
+    let args = ["-o", "pdf", "--label", "Backup key"];
+    let pdf = encode_with_args(&data, &args, &None)?;
+    assert!(pdf.starts_with(b"%PDF-"));
+    let pdf = String::from_utf8_lossy(&pdf);
+    for part in 1..=parts {
+        assert!(pdf.contains(&format!("(part {part} of {parts}) Tj")));
+    }
+    assert!(pdf.contains("(Backup key) Tj"));
+    assert!(pdf.contains("(To restore, install qrstream"));
+
+    // the rectangles filled for each code draw its modules, at 0.5 mm each
+    let module = 0.5 * 72.0 / 25.4;
+    let mut codes = vec![];
+    for content in pdf.split("\nstream\n").skip(1) {
+        let content = &content[..content.find("endstream").unwrap()];
+        let mut rects = vec![];
+        for line in content.lines() {
+            if line.ends_with(" re") {
+                let values = line
+                    .split_whitespace()
+                    .take(4)
+                    .map(|value| value.parse::<f32>().unwrap() / module)
+                    .collect::<Vec<_>>();
+                rects.push((values[0], values[1] + values[3], values[2]));
+            } else if line == "f" {
+                codes.push(std::mem::take(&mut rects));
+            }
+        }
+    }
+    assert_eq!(parts, codes.len());
+    for (line, rects) in lines.lines().zip(codes) {
+        let qr = qr_code::QrCode::with_error_correction_level(line, qr_code::EcLevel::Q)?;
+        // the top left module is always dark, as part of a finder pattern
+        let left = rects.iter().map(|r| r.0).fold(f32::MAX, f32::min);
+        let top = rects.iter().map(|r| r.1).fold(f32::MIN, f32::max);
+        let mut modules = vec![false; qr.width() * qr.width()];
+        for (x, y, width) in rects {
+            let row = (top - y).round() as usize;
+            let col = (x - left).round() as usize;
+            for idx in 0..width.round() as usize {
+                modules[row * qr.width() + col + idx] = true;
+            }
+        }
+        assert_eq!(qr.to_vec(), modules);
+    }
+
+    let args = ["-o", "pdf", "--module-size", "5"];
+    let stderr = Command::cargo_bin(QRSTREAM_CMD)?
+        .arg("encode")
+        .args(args)
+        .write_stdin(data)
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .to_owned();
+    assert!(String::from_utf8(stderr)?.contains("use a smaller --module-size"));
+
+    Ok(())
+}
+
+#[test]
+fn test_format_term_encode() -> Result<(), Box<dyn std::error::Error>> {
+    let data = rand_text(3_000);
+    let lines = String::from_utf8(encode(&data, "txt", &None)?)?;
+    let term = String::from_utf8(encode(&data, "term", &None)?)?;
+    let parts = lines.lines().count();
+    assert!(parts > 1);
+
+    // each character covers 2 modules, one above the other, with a quiet zone of 4 modules
+    let mut codes = term.split_terminator("\n\n");
+    for (idx, line) in lines.lines().enumerate() {
+        let code = codes.next().unwrap();
+        let (blocks, caption) = code.rsplit_once('\n').unwrap();
+        assert_eq!(format!("part {} of {parts}", idx + 1), caption);
+        let qr = qr_code::QrCode::with_error_correction_level(line, qr_code::EcLevel::Q)?;
+        let size = qr.width() + 8;
+        let mut modules = vec![];
+        for row in blocks.lines() {
+            let row = row
+                .strip_prefix("\x1b[38;5;16;48;5;231m")
+                .and_then(|row| row.strip_suffix("\x1b[0m"))
+                .unwrap();
+            let (top, bottom): (Vec<_>, Vec<_>) = row
+                .chars()
+                .map(|c| (c == '█' || c == '▀', c == '█' || c == '▄'))
+                .unzip();
+            assert_eq!(size, top.len());
+            modules.extend(top);
+            modules.extend(bottom);
+        }
+        let modules = modules
+            .chunks(size)
+            .skip(4)
+            .take(qr.width())
+            .flat_map(|row| row[4..4 + qr.width()].to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(qr.to_vec(), modules);
+    }
+
+    // kitty images are PNGs sent in base64 chunks
+    let data = "Hello World";
+    let args = ["-o", "term", "--term-graphics", "kitty"];
+    let kitty = String::from_utf8(encode_with_args(data, &args, &None)?)?;
+    let png = kitty
+        .split("\x1b_G")
+        .skip(1)
+        .map(|chunk| &chunk[chunk.find(';').unwrap() + 1..chunk.find("\x1b\\").unwrap()])
+        .collect::<String>();
+    let png = base64::engine::general_purpose::STANDARD.decode(png)?;
+    assert_eq!(data, decode(&png, &None)?);
+
+    let args = ["-o", "term", "--term-graphics", "sixel"];
+    let sixel = String::from_utf8(encode_with_args(data, &args, &None)?)?;
+    assert!(sixel.starts_with("\x1bPq\""));
+    assert!(sixel.contains("\x1b\\\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_format_animated_encode() -> Result<(), Box<dyn std::error::Error>> {
+    use image::AnimationDecoder;
+
+    let data = rand_text(3_000);
+    let parts = String::from_utf8(encode(&data, "txt", &None)?)?
+        .lines()
+        .count();
+    assert!(parts > 1);
+    let dir = temp_dir()?;
+    // each frame has a single code, and all frames together decode to the data
+    let decode_frames = |format: &str,
+                         frames: Vec<image::GrayImage>|
+     -> Result<String, Box<dyn std::error::Error>> {
+        assert_eq!(parts, frames.len());
+        let mut args = vec![];
+        for (idx, frame) in frames.iter().enumerate() {
+            let path = dir.join(format!("{format}-{idx}.png"));
+            frame.save(&path)?;
+            args.extend(["-i".to_string(), path.to_str().unwrap().to_string()]);
+        }
+        let output = Command::cargo_bin(QRSTREAM_CMD)?
+            .args(args)
+            .arg("decode")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .to_owned();
+        Ok(String::from_utf8(output)?)
+    };
+
+    let gif = encode_with_args(&data, &["-o", "gif", "--frame-rate", "4"], &None)?;
+    let frames = image::codecs::gif::GifDecoder::new(gif.as_slice())?
+        .into_frames()
+        .collect_frames()?;
+    assert_eq!((250, 1), frames[0].delay().numer_denom_ms());
+    let frames = frames
+        .into_iter()
+        .map(|frame| image::DynamicImage::ImageRgba8(frame.into_buffer()).into_luma8())
+        .collect();
+    assert_eq!(data, decode_frames("gif", frames)?);
+
+    let apng = encode_with_args(&data, &["-o", "apng", "--frame-rate", "0.5"], &None)?;
+    let mut reader = png::Decoder::new(apng.as_slice()).read_info()?;
+    let (width, height) = reader.info().size();
+    let mut frames = vec![];
+    let mut buf = vec![0; reader.output_buffer_size()];
+    while frames.len() < parts {
+        reader.next_frame(&mut buf)?;
+        let control = reader.info().frame_control().unwrap();
+        assert_eq!(2.0, control.delay_num as f32 / control.delay_den as f32);
+        frames.push(image::GrayImage::from_raw(width, height, buf.clone()).unwrap());
+    }
+    assert_eq!(
+        Some(parts as u32),
+        reader.info().animation_control().map(|c| c.num_frames)
+    );
+    assert_eq!(data, decode_frames("apng", frames)?);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_format_txt_many_parts() -> Result<(), Box<dyn std::error::Error>> {
+    let data = rand_text(30_000);
+    let stdout = String::from_utf8(encode(&data, "txt", &None)?)?;
+    assert!(stdout.lines().count() > 15);
+    assert!(stdout.starts_with(&format!("{QRSTREAM_MAGIC}/{QRSTREAM_VERSION};p=1/")));
+    let decoded = decode(stdout.as_bytes(), &None)?;
+    assert_eq!(data, decoded);
+
+    Ok(())
+}
+
+#[test]
+fn test_format_v1_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let decoded = decode(b"QRST/1;p=11;t=SGVsbG8gV29ybGQ", &None)?;
+    assert_eq!("Hello World", decoded);
+
+    Ok(())
+}
+
+#[test]
+fn test_format_v2_base64_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let decoded = decode(
+        b"QRST/2;p=1/1;t=AC4BAAEABAAEAAAACwYAIKWRptQL9CBASgEXM8-3sZDWLGW_C82jK1eyd9mtnxRuSGVsbG8gV29ybGQ",
+        &None,
+    )?;
+    assert_eq!("Hello World", decoded);
+
+    Ok(())
+}
+
+#[test]
+fn test_password_not_needed() -> Result<(), Box<dyn std::error::Error>> {
+    let encoded = encode("Hello World", "txt", &None)?;
+    let password = Some(format!("key:{}", "ab".repeat(32)));
+    let stderr = decode_failure(&encoded, &password)?;
+    assert!(stderr.contains("stream is not encrypted"));
+
+    Ok(())
+}
+
+#[test]
+fn test_corrupt_part() -> Result<(), Box<dyn std::error::Error>> {
+    let data = rand_text(5_000);
+    let stdout = String::from_utf8(encode(&data, "txt", &None)?)?;
+    let mut lines = stdout.lines().map(|l| l.to_owned()).collect::<Vec<_>>();
+    let num_parts = lines.len();
+    assert!(num_parts > 1);
+    // flip the last character of the second part
+    let last = lines[1].pop().unwrap();
+    lines[1].push(if last == 'A' { 'B' } else { 'A' });
+    let stderr = decode_failure(lines.join("\n").as_bytes(), &None)?;
+    assert!(stderr.contains(&format!("corrupt part: 2 of {num_parts}")));
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_version_decode() -> Result<(), Box<dyn std::error::Error>> {
+    for encoded in ["QRST;a=1", "QRST2;a=1", "QRST/;a=1", "QRST/x;a=1"] {
+        let stderr = decode_failure(encoded.as_bytes(), &None)?;
+        assert!(stderr.contains("invalid version str"));
+    }
+    let stderr = decode_failure(b"QRST/9;p=1/1;a=AA", &None)?;
+    assert!(stderr.contains("unsupported version"));
+
+    Ok(())
+}
+
+#[test]
+fn test_forged_part_total() -> Result<(), Box<dyn std::error::Error>> {
+    // totals far beyond the parts received are refused, rather than allocated for
+    let stderr = decode_failure(b"QRST/2;p=1/ffffffffffffffff;a=AA", &None)?;
+    assert!(stderr.contains("incomplete list of input"));
+    // parity parts can't make up for more than Reed-Solomon's 256 shards
+    let stderr = decode_failure(b"QRST/2;p=1/1000;r=fff;a=AA", &None)?;
+    assert!(stderr.contains("invalid parity information"));
+
+    Ok(())
+}
+
+#[test]
+fn test_decompression_bomb() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Read;
+    let encoded = encode("Hello World", "txt", &None)?;
+    let decode_payload = |compressed: &[u8], metadata: bool| {
+        let forged = with_single_part_payload(&encoded, &zstd_payload(compressed, metadata));
+        decode_failure(&forged, &None)
+    };
+
+    // the stream built by hand decodes, as long as it doesn't decompress to too much
+    let compressed = zstd::encode_all("Hello World".as_bytes(), 3)?;
+    let forged = with_single_part_payload(&encoded, &zstd_payload(&compressed, false));
+    assert_eq!("Hello World", decode(&forged, &None)?);
+
+    // a couple of KB of zeros, decompressing to just over the limit of 64 MiB
+    let zeros = std::io::repeat(0).take(64 * 1024 * 1024 + 1);
+    let stderr = decode_payload(&zstd::encode_all(zeros, 3)?, false)?;
+    assert!(stderr.contains("invalid compressed data"));
+
+    // with metadata, nothing is decompressed beyond the length it tells
+    let mut data = vec![0, 11, 3, 0, 8];
+    data.extend_from_slice(&11u64.to_be_bytes());
+    data.extend_from_slice(b"Hello World");
+    let compressed = zstd::encode_all(data.as_slice(), 3)?;
+    let forged = with_single_part_payload(&encoded, &zstd_payload(&compressed, true));
+    assert_eq!("Hello World", decode(&forged, &None)?);
+    let zeros = std::io::repeat(0).take(1024 * 1024);
+    let stderr = decode_payload(&zstd::encode_all(data.chain(zeros), 3)?, true)?;
+    assert!(stderr.contains("invalid compressed data"));
+
+    Ok(())
+}
+
+/// Payload of an unencrypted stream, with the header built by hand, as a forged stream would
+/// have it, for `compressed` data compressed with zstd.
+fn zstd_payload(compressed: &[u8], metadata: bool) -> Vec<u8> {
+    let mut entries = vec![1, 0, 1, 0];
+    entries.extend_from_slice(&[4, 0, 4]);
+    entries.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+    entries.extend_from_slice(&[7, 0, 1, 2]);
+    if metadata {
+        entries.extend_from_slice(&[8, 0, 0]);
+    }
+    let mut payload = (entries.len() as u16).to_be_bytes().to_vec();
+    payload.extend(entries);
+    payload.extend_from_slice(compressed);
+    payload
+}
+
+#[test]
+fn test_signed_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = temp_dir()?;
+    let signing_key = dir.join("signing.pem");
+    std::fs::write(&signing_key, SIGNING_KEY)?;
+    let verifying_key = dir.join("verifying.pem");
+    std::fs::write(&verifying_key, VERIFYING_KEY)?;
+    let other_verifying_key = dir.join("other-verifying.pem");
+    std::fs::write(&other_verifying_key, OTHER_VERIFYING_KEY)?;
+    let data = "Hello World";
+    let sign_args = ["-o", "txt", "--sign-key", signing_key.to_str().unwrap()];
+    let encoded = encode_with_args(data, &sign_args, &None)?;
+    let verify_args = ["--verify-key", verifying_key.to_str().unwrap()];
+    assert_eq!(data, decode_with_args(&encoded, &verify_args, &None)?);
+    // the signature is only checked when asked for
+    assert_eq!(data, decode(&encoded, &None)?);
+
+    let other_verify_args = ["--verify-key", other_verifying_key.to_str().unwrap()];
+    let stderr = decode_failure_with_args(&encoded, &other_verify_args, &None)?;
+    assert!(stderr.contains("signature verification failed"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_shares_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = temp_dir()?;
+    let data = rand_text(1000);
+    let stdout = Command::cargo_bin(QRSTREAM_CMD)?
+        .current_dir(&dir)
+        .args(["encode", "-o", "txt", "--shares", "5", "--threshold", "3"])
+        .write_stdin(data.as_str())
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .to_owned();
+    // all the shares go to stdout, nothing is written to disk
+    assert_eq!(0, std::fs::read_dir(&dir)?.count());
+    let stdout = String::from_utf8(stdout)?;
+    let decode_shares = |shares: &[usize]| {
+        let lines = stdout
+            .lines()
+            .filter(|line| {
+                shares
+                    .iter()
+                    .any(|x| line.contains(&format!(";s={x:x}/3;")))
+            })
+            .collect::<Vec<_>>();
+        Command::cargo_bin(QRSTREAM_CMD)
+            .unwrap()
+            .arg("decode")
+            .write_stdin(lines.join("\n"))
+            .assert()
+    };
+    for shares in [[1, 2, 3], [5, 2, 4]] {
+        let stdout = decode_shares(&shares)
+            .success()
+            .get_output()
+            .stdout
+            .to_owned();
+        assert_eq!(data, String::from_utf8(stdout)?);
+    }
+    assert_eq!(data, decode(stdout.as_bytes(), &None)?);
+    let stderr = decode_shares(&[1, 5])
+        .failure()
+        .get_output()
+        .stderr
+        .to_owned();
+    assert!(String::from_utf8(stderr)?.contains("not enough shares: 2 of 3 needed"));
+
+    // formats that can't hold a sheet for each share are refused
+    for format in ["term", "gif", "apng"] {
+        let args = ["-o", format, "--shares", "5", "--threshold", "3"];
+        Command::cargo_bin(QRSTREAM_CMD)?
+            .arg("encode")
+            .args(args)
+            .write_stdin(data.as_str())
+            .assert()
+            .failure();
+    }
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_shares_labels() -> Result<(), Box<dyn std::error::Error>> {
+    let data = rand_text(500);
+    let encode_shares = |format: &str| {
+        encode_with_args(
+            &data,
+            &["-o", format, "--shares", "3", "--threshold", "2"],
+            &None,
+        )
+    };
+    let svg = String::from_utf8(encode_shares("svg")?)?;
+    let pdf = encode_shares("pdf")?;
+    for x in 1..=3 {
+        let label = format!("share {x} of 3, 2 needed");
+        assert!(svg.contains(&format!(">{label}</text>")));
+        assert!(String::from_utf8_lossy(&pdf).contains(&format!("({label}) Tj")));
+    }
+
+    // the sheets of the PNG are stacked, each with the label drawn in a band above its codes
+    let png = encode_shares("png")?;
+    let img = image::load_from_memory(&png)?.to_luma8();
+    let sheet_height = img.height() / 3;
+    let sheet = |x: u32| {
+        let top = (x - 1) * sheet_height;
+        assert!((top..top + 48).any(|y| (0..img.width()).any(|x| img.get_pixel(x, y)[0] == 0)));
+        let sheet = image::imageops::crop_imm(&img, 0, top, img.width(), sheet_height);
+        let mut png = std::io::Cursor::new(Vec::<u8>::new());
+        sheet
+            .to_image()
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        png.into_inner()
+    };
+    let dir = temp_dir()?;
+    for x in [1, 3] {
+        std::fs::write(dir.join(format!("share-{x}.png")), sheet(x))?;
+    }
+    let stdout = Command::cargo_bin(QRSTREAM_CMD)?
+        .current_dir(&dir)
+        .args(["-i", "share-1.png", "-i", "share-3.png"])
+        .arg("decode")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .to_owned();
+    assert_eq!(data, String::from_utf8(stdout)?);
+    assert_eq!(data, decode(&png, &None)?);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_parity_recovery() -> Result<(), Box<dyn std::error::Error>> {
+    let data = rand_text(5_000);
+    let stdout = String::from_utf8(encode_with_args(
+        &data,
+        &["-o", "txt", "--parity", "2"],
+        &None,
+    )?)?;
+    let mut lines = stdout.lines().map(|l| l.to_owned()).collect::<Vec<_>>();
+    assert!(lines[0].contains(";r=2;"));
+    // lose the first part, and corrupt the last one
+    lines.remove(0);
+    let last = lines.last_mut().unwrap();
+    let ch = last.pop().unwrap();
+    last.push(if ch == 'A' { 'B' } else { 'A' });
+    let decoded = decode(lines.join("\n").as_bytes(), &None)?;
+    assert_eq!(data, decoded);
+
+    // a third missing part is one too many
+    lines.remove(0);
+    let stderr = decode_failure(lines.join("\n").as_bytes(), &None)?;
+    assert!(stderr.contains("corrupt part"));
+
+    Ok(())
+}
+
+#[test]
+fn test_parity_png_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let data = "Hello World";
+    let encoded = encode_with_args(data, &["-o", "png", "--parity", "1"], &None)?;
+    let decoded = decode(&encoded, &None)?;
+    assert_eq!(data, decoded);
+
+    Ok(())
+}
+
+#[test]
+fn test_fountain_decode() -> Result<(), Box<dyn std::error::Error>> {
+    let data = rand_text(5_000);
+    let stdout = String::from_utf8(encode_with_args(
+        &data,
+        &["-o", "txt", "--fountain", "30"],
+        &None,
+    )?)?;
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 30);
+    assert!(lines[0].starts_with(&format!("{QRSTREAM_MAGIC}/{QRSTREAM_VERSION};f=1/")));
+
+    // without the first few codes, out of order and with duplicates
+    let mut shuffled = lines[4..].to_vec();
+    shuffled.reverse();
+    shuffled.extend_from_slice(&lines[10..20]);
+    let decoded = decode(shuffled.join("\n").as_bytes(), &None)?;
+    assert_eq!(data, decoded);
+
+    // far too few codes
+    let stderr = decode_failure(lines[..2].join("\n").as_bytes(), &None)?;
+    assert!(stderr.contains("incomplete list of input"));
+
+    // block counts that the codes received can't cover, or beyond the limit, are refused
+    // rather than allocated for
+    let stderr = decode_failure(b"QRST/2;f=1/fff;a=AAA", &None)?;
+    assert!(stderr.contains("incomplete list of input"));
+    let stderr = decode_failure(b"QRST/2;f=1/fffffffffff;a=AAA", &None)?;
+    assert!(stderr.contains("invalid fountain code information"));
+
+    Ok(())
+}
+
+#[test]
+fn test_compress_auto_incompressible() -> Result<(), Box<dyn std::error::Error>> {
+    // too short to shrink, so auto leaves it uncompressed
+    let data = rand_text(16);
+    let plain = encode(&data, "txt", &None)?;
+    let auto = encode_with_args(&data, &["-o", "txt", "--compress", "auto"], &None)?;
+    assert_eq!(auto.len(), plain.len());
+    let decoded = decode(&auto, &None)?;
+    assert_eq!(data, decoded);
+
+    Ok(())
+}
+
+#[test]
+fn test_structured_append_decode() -> Result<(), Box<dyn std::error::Error>> {
+    // small enough to stay clear of the largest code versions, which rqrr doesn't always locate
+    let data = rand_text(1_000);
+    let encoded = encode_with_args(&data, &["--structured-append", "--parity", "1"], &None)?;
+    let decoded = decode(&encoded, &None)?;
+    assert_eq!(data, decoded);
+
+    Ok(())
+}
+
+#[test]
+fn test_foreign_structured_append_decode() -> Result<(), Box<dyn std::error::Error>> {
+    // another tool splitting the txt output at arbitrary points, into byte mode codes. The data
+    // is fixed, as rqrr can't locate every grid, and the codes of random data may hit that
+    let data = "Hello World\n".repeat(80);
+    let stdout = encode(&data, "txt", &None)?;
+    let codes = qr_code::structured::SplittedQr::new(stdout, 16)?.split()?;
+    assert!(codes.len() > 2);
+
+    // in reverse, as the order of the codes is up to the structured append header
+    let decoded = decode(&codes_png(codes.iter().rev())?, &None)?;
+    assert_eq!(data, decoded);
+
+    // pieces of a set can't be told apart from a corrupt stream, so the set is reported
+    let stderr = decode_failure(&codes_png(codes.iter().skip(1))?, &None)?;
+    assert!(stderr.contains(&format!(
+        "incomplete structured append set: {} of {} codes",
+        codes.len() - 1,
+        codes.len()
+    )));
+
+    Ok(())
+}
+
+/// Draws `codes` one below the other, into a PNG.
+fn codes_png<'a>(
+    codes: impl Iterator<Item = &'a qr_code::QrCode>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let codes = codes.collect::<Vec<_>>();
+    let scale = 4;
+    let quiet_zone = 4 * scale;
+    let size = codes[0].width() as u32 * scale + 2 * quiet_zone;
+    let mut img = image::GrayImage::from_pixel(size, size * codes.len() as u32, image::Luma([255]));
+    for (idx, qr) in codes.iter().enumerate() {
+        let top = idx as u32 * size + quiet_zone;
+        for (i, dark) in qr.to_vec().into_iter().enumerate() {
+            let (x, y) = ((i % qr.width()) as u32, (i / qr.width()) as u32);
+            for (dx, dy) in (0..scale).flat_map(|dx| (0..scale).map(move |dy| (dx, dy))) {
+                if dark {
+                    img.put_pixel(
+                        quiet_zone + x * scale + dx,
+                        top + y * scale + dy,
+                        image::Luma([0]),
+                    );
+                }
+            }
+        }
+    }
+    let mut png = std::io::Cursor::new(Vec::<u8>::new());
+    img.write_to(&mut png, image::ImageOutputFormat::Png)?;
+    Ok(png.into_inner())
+}